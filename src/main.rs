@@ -28,6 +28,7 @@ use directories::ProjectDirs;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt::{self, Debug};
 use std::fs::{self, File};
@@ -35,7 +36,10 @@ use std::io::{self, Read, Write};
 use std::net::SocketAddr;
 use std::panic;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
 use tracing::{debug, enabled, error, info, trace, warn, Level};
@@ -70,10 +74,36 @@ const PKG_REPOSITORY_O: Option<&str> = option_env!("CARGO_PKG_REPOSITORY");
 const PKG_REPOSITORY: &str = "https://github.com/8go/nostr-commander-rs/";
 /// default name for login credentials JSON file
 const CREDENTIALS_FILE_DEFAULT: &str = "credentials.json";
+/// name of the local event store file, kept next to the credentials file
+const EVENT_STORE_FILE_DEFAULT: &str = "events.jsonl";
+/// name of the file recording which filters the event store is in sync with
+const EVENT_SYNC_FILE_DEFAULT: &str = "events-synced.json";
+/// name of the outbox file, kept next to the credentials file
+const OUTBOX_FILE_DEFAULT: &str = "outbox.json";
+/// first retry of an event in the outbox after this many seconds, doubling with each attempt
+const OUTBOX_BACKOFF_DEFAULT: u64 = 60;
+/// longest wait between retries of an event in the outbox, in seconds
+const OUTBOX_BACKOFF_MAX: u64 = 24 * 60 * 60;
+/// default timeout for '--wait-ok' in seconds
+const WAIT_OK_TIMEOUT_DEFAULT: u64 = 20;
+/// default number of relays that must accept each event with '--wait-ok'
+const QUORUM_DEFAULT: usize = 1;
+/// ephemeral kind of the test event sent by '--check-relays', relays do not store it
+const RELAY_CHECK_KIND: u16 = 20_001;
+/// maximum number of inbox relays of a recipient that an event is also sent to
+const INBOX_RELAYS_MAX: usize = 3;
+/// kind of the relay list for private messages (NIP-17)
+const DM_RELAY_LIST_KIND: u16 = 10_050;
 // /// default timeouts for waiting for the Nostr server, in seconds
 // const TIMEOUT_DEFAULT: u64 = 60;
+/// default timeout for fetching stored events from relays, in seconds
+const FETCH_TIMEOUT_DEFAULT: u64 = 10;
+/// NIP-59 allows gift wraps to be backdated by up to 2 days, in seconds
+const GIFT_WRAP_TWEAK: u64 = 2 * 24 * 60 * 60;
 /// default POW difficulty
 const POW_DIFFICULTY_DEFAULT: u8 = 20;
+/// highest POW difficulty, each bit doubles the mining time
+const POW_DIFFICULTY_MAX: u8 = 32;
 /// URL for README.md file downloaded for --readme
 const URL_README: &str = "https://raw.githubusercontent.com/8go/nostr-commander-rs/main/README.md";
 
@@ -116,12 +146,54 @@ pub enum Error {
     #[error("Publish Failed")]
     PublishFailed,
 
+    #[error("Change Metadata Failed")]
+    ChangeMetadataFailed,
+
+    #[error("Show Metadata Failed")]
+    ShowMetadataFailed,
+
+    #[error("Delete User Failed")]
+    DeleteUserFailed,
+
     #[error("Publish POW Failed")]
     PublishPowFailed,
 
+    #[error("React Failed")]
+    ReactFailed,
+
+    #[error("Repost Failed")]
+    RepostFailed,
+
     #[error("DM Failed")]
     DmFailed,
 
+    #[error("Decrypting DM Failed")]
+    DecryptDmFailed,
+
+    #[error("DM History Failed")]
+    DmHistoryFailed,
+
+    #[error("Fetch Failed")]
+    FetchFailed,
+
+    #[error("Invalid Filter")]
+    InvalidFilter,
+
+    #[error("Event Store Failed")]
+    EventStoreFailed,
+
+    #[error("Outbox Failed")]
+    OutboxFailed,
+
+    #[error("Quorum Not Reached")]
+    QuorumNotReached,
+
+    #[error("Invalid Proxy")]
+    InvalidProxy,
+
+    #[error("Proxy Failed")]
+    ProxyFailed,
+
     #[error("Send Failed")]
     SendFailed,
 
@@ -149,6 +221,15 @@ pub enum Error {
     #[error("Invalid Hash")]
     InvalidHash,
 
+    #[error("Invalid Event Id")]
+    InvalidEventId,
+
+    #[error("Invalid Tag")]
+    InvalidTag,
+
+    #[error("Fetch Event Failed")]
+    FetchEventFailed,
+
     #[error("Unknown CLI parameter")]
     UnknownCliParameter,
 
@@ -192,6 +273,71 @@ impl Error {
     pub(crate) fn custom<T>(message: &'static str) -> Result<T, Error> {
         Err(Error::Custom(message))
     }
+
+    /// The process exit code reported for this error.
+    /// - 0: success
+    /// - 1: any other failure
+    /// - 2: invalid command line arguments
+    /// - 3: credentials missing, unreadable or not writable
+    /// - 4: invalid key, event id or hash
+    /// - 5: relays cannot be connected or do not answer
+    /// - 6: events were rejected or could not be published
+    /// - 7: partial success, events were not accepted by enough relays
+    pub(crate) fn exit_code(&self) -> u8 {
+        match self {
+            Error::UnknownCliParameter
+            | Error::UnsupportedCliParameter(_)
+            | Error::MissingCliParameter
+            | Error::MissingUser
+            | Error::MissingPassword
+            | Error::InvalidFilter
+            | Error::InvalidTag
+            | Error::InvalidProxy => 2,
+            Error::NoHomeDirectory
+            | Error::UserAlreadyExists
+            | Error::StorageFailure
+            | Error::InvalidFile
+            | Error::CreatingUserFailed
+            | Error::ReadingCredentialsFailed
+            | Error::DeleteUserFailed
+            | Error::NoCredentialsFound => 3,
+            Error::KeyFailure
+            | Error::ConversionFailed
+            | Error::InvalidKey
+            | Error::InvalidHash
+            | Error::InvalidEventId
+            | Error::NostrKey(_) => 4,
+            Error::CannotConnectToRelays
+            | Error::AddRelayFailed
+            | Error::InvalidClientConnection
+            | Error::FetchFailed
+            | Error::FetchEventFailed
+            | Error::ListenFailed
+            | Error::SubscriptionFailed
+            | Error::UnsubscribeFailed
+            | Error::GetEntityFailed
+            | Error::ProxyFailed => 5,
+            Error::PublishFailed
+            | Error::PublishPowFailed
+            | Error::ChangeMetadataFailed
+            | Error::ReactFailed
+            | Error::RepostFailed
+            | Error::DmFailed
+            | Error::SendFailed
+            | Error::SendChannelFailed => 6,
+            Error::QuorumNotReached | Error::OutboxFailed => 7,
+            Error::Custom(_)
+            | Error::ShowMetadataFailed
+            | Error::DecryptDmFailed
+            | Error::DmHistoryFailed
+            | Error::EventStoreFailed
+            | Error::NotImplementedYet
+            | Error::IO(_)
+            | Error::NostrNip04(_)
+            | Error::Json(_)
+            | Error::Anyhow(_) => 1,
+        }
+    }
 }
 
 // impl From<anyhow::Error> for Error {
@@ -209,7 +355,7 @@ enum Version {
     Check,
 }
 
-/// is_ functions for the enum
+// is_ functions for the enum
 // impl Version {
 //     pub fn is_check(&self) -> bool {
 //         self == &Self::Check
@@ -220,10 +366,10 @@ enum Version {
 impl FromStr for Version {
     type Err = ();
     fn from_str(src: &str) -> Result<Version, ()> {
-        return match src.to_lowercase().trim() {
+        match src.to_lowercase().trim() {
             "check" => Ok(Version::Check),
             _ => Err(()),
-        };
+        }
     }
 }
 
@@ -236,6 +382,121 @@ impl fmt::Display for Version {
     }
 }
 
+/// Enumerator used for --show-metadata option
+#[derive(Clone, Debug, Copy, PartialEq, Default, ValueEnum)]
+enum ShowMetadata {
+    /// Remote: Additionally fetch the metadata from the relays and compare
+    #[default]
+    Remote,
+}
+
+/// Converting from String to ShowMetadata for --show-metadata option
+impl FromStr for ShowMetadata {
+    type Err = ();
+    fn from_str(src: &str) -> Result<ShowMetadata, ()> {
+        match src.to_lowercase().trim() {
+            "remote" => Ok(ShowMetadata::Remote),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Creates .to_string() for ShowMetadata for --show-metadata option
+impl fmt::Display for ShowMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Enumerator used for --delete-user option
+#[derive(Clone, Debug, Copy, PartialEq, Default, ValueEnum)]
+enum DeleteUser {
+    /// Remote: Additionally ask the relays to delete the user's data
+    #[default]
+    Remote,
+}
+
+/// Converting from String to DeleteUser for --delete-user option
+impl FromStr for DeleteUser {
+    type Err = ();
+    fn from_str(src: &str) -> Result<DeleteUser, ()> {
+        match src.to_lowercase().trim() {
+            "remote" => Ok(DeleteUser::Remote),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Creates .to_string() for DeleteUser for --delete-user option
+impl fmt::Display for DeleteUser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Enumerator used for --dm-protocol option
+#[derive(Clone, Debug, Copy, PartialEq, Default, ValueEnum)]
+enum DmProtocol {
+    /// Nip17: Gift-wrapped private DMs (kind 14 in kind 1059), default
+    #[default]
+    Nip17,
+    /// Nip04: Legacy encrypted DMs (kind 4), leak metadata
+    Nip04,
+}
+
+/// Converting from String to DmProtocol for --dm-protocol option
+impl FromStr for DmProtocol {
+    type Err = ();
+    fn from_str(src: &str) -> Result<DmProtocol, ()> {
+        match src.to_lowercase().replace('-', "").trim() {
+            "nip17" => Ok(DmProtocol::Nip17),
+            "nip04" | "nip4" => Ok(DmProtocol::Nip04),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Creates .to_string() for DmProtocol for --dm-protocol option
+impl fmt::Display for DmProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Enumerator used for --relay-flags option
+#[derive(Clone, Debug, Copy, PartialEq, ValueEnum)]
+enum RelayFlag {
+    /// Read: subscribe and fetch events from the relay
+    Read,
+    /// Write: publish events to the relay
+    Write,
+    /// Inbox: others should send events mentioning us to this relay (NIP-65 read)
+    Inbox,
+    /// Outbox: others should look for our events on this relay (NIP-65 write)
+    Outbox,
+}
+
+/// Converting from String to RelayFlag for --relay-flags option
+impl FromStr for RelayFlag {
+    type Err = ();
+    fn from_str(src: &str) -> Result<RelayFlag, ()> {
+        match src.to_lowercase().trim() {
+            "read" => Ok(RelayFlag::Read),
+            "write" => Ok(RelayFlag::Write),
+            "inbox" => Ok(RelayFlag::Inbox),
+            "outbox" => Ok(RelayFlag::Outbox),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Creates .to_string() for RelayFlag for --relay-flags option
+impl fmt::Display for RelayFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 /// Enumerator used for --log-level option
 #[derive(Clone, Debug, Copy, PartialEq, Default, ValueEnum)]
 enum LogLevel {
@@ -295,20 +556,22 @@ impl Output {
         self == &Self::Text
     }
 
-    // pub fn is_json_spec(&self) -> bool { self == &Self::JsonSpec }
+    pub fn is_json_spec(&self) -> bool {
+        self == &Self::JsonSpec
+    }
 }
 
 /// Converting from String to Listen for --listen option
 impl FromStr for Output {
     type Err = ();
     fn from_str(src: &str) -> Result<Output, ()> {
-        return match src.to_lowercase().replace('-', "_").trim() {
+        match src.to_lowercase().replace('-', "_").trim() {
             "text" => Ok(Output::Text),
             "json" => Ok(Output::Json),
             "jsonmax" | "json_max" => Ok(Output::JsonMax), // accept all 3: jsonmax, json-max, json_max
             "jsonspec" | "json_spec" => Ok(Output::JsonSpec),
             _ => Err(()),
-        };
+        }
     }
 }
 
@@ -351,6 +614,10 @@ pub struct Args {
     #[arg(skip)]
     creds: Credentials,
 
+    // The local SOCKS5 bridges to proxies with username and password.
+    #[arg(skip)]
+    bridges: Socks5Bridges,
+
     /// Please contribute.
     #[arg(long, default_value_t = false)]
     contribute: bool,
@@ -489,13 +756,30 @@ pub struct Args {
     /// This will erase the key pair and other associated information
     /// like user name, display name, etc. Afterwards one can create
     /// a new user with '--create-user'.
-    #[arg(long, alias = "delete-key", default_value_t = false)]
-    delete_user: bool,
+    /// The credentials file, the event store and the outbox are
+    /// first overwritten and then removed.
+    /// Before deleting, you will be asked to confirm by typing 'yes'.
+    /// Use '--yes' to skip this question, e.g. in scripts.
+    /// If 'remote' is added ('--delete-user remote') then, before the
+    /// keys are destroyed, an empty metadata event (kind 0) is published
+    /// and the relays are asked to delete all your past events by
+    /// publishing deletion events (kind 5, NIP-09). Relays are not
+    /// obliged to honour deletion requests.
+    /// It cannot be combined with '--nsec', it always deletes the user
+    /// of the credentials file.
+    #[arg(long, alias = "delete-key", value_name = "REMOTE")]
+    delete_user: Option<Option<DeleteUser>>,
+
+    /// Answer 'yes' to all confirmation questions.
+    /// Details:: Used e.g. together with '--delete-user' to avoid
+    /// being asked for confirmation on the keyboard.
+    #[arg(short, long, default_value_t = false)]
+    yes: bool,
 
     /// Specify an optional user name.
     /// Details::
     /// Used together with
-    /// '--create-user'.
+    /// '--create-user' or '--change-metadata'.
     /// If this option is not set during '--create-user', the information
     /// will be queried via the keyboard. If you want to set it to empty
     /// and not be queried, provide an empty string ''.
@@ -505,7 +789,7 @@ pub struct Args {
     /// Specify an optional display name.
     /// Details::
     /// Used together with
-    /// '--create-user'.
+    /// '--create-user' or '--change-metadata'.
     /// If this option is not set during '--create-user', the information
     /// will be queried via the keyboard. If you want to set it to empty
     /// and not be queried, provide an empty string ''.
@@ -515,7 +799,7 @@ pub struct Args {
     /// Specify an optional description.
     /// Details::
     /// Used together with
-    /// '--create-user'.
+    /// '--create-user' or '--change-metadata'.
     /// If this option is not set during '--create-user', the information
     /// will be queried via the keyboard. If you want to set it to empty
     /// and not be queried, provide an empty string ''.
@@ -524,7 +808,7 @@ pub struct Args {
 
     /// Specify an optional picture or avatar.
     /// Details:: Used together with
-    /// '--create-user'. Provide a URL like 'https://example.com/avatar.png'.
+    /// '--create-user' or '--change-metadata'. Provide a URL like 'https://example.com/avatar.png'.
     // or a local file like 'file://somepath/someimage.jpg'.
     /// If this option is not set during '--create-user', the information
     /// will be queried via the keyboard. If you want to set it to empty
@@ -535,7 +819,7 @@ pub struct Args {
     /// Specify an optional nip05 name.
     /// Details::
     /// Used together with
-    /// '--create-user'. Provide a nip05 name like 'john@example.org'.
+    /// '--create-user' or '--change-metadata'. Provide a nip05 name like 'john@example.org'.
     /// If this option is not set during '--create-user', the information
     /// will be queried via the keyboard. If you want to set it to empty
     /// and not be queried, provide an empty string ''.
@@ -600,14 +884,53 @@ pub struct Args {
     #[arg(short, long, value_name = "NOTE", num_args(0..), )]
     publish: Vec<String>,
 
+    /// Publish the notes as reply to an event.
+    /// Details:: Used together with '--publish' or '--publish-pow'. Specify the event
+    /// to reply to by its Hex id, note or nevent. The event is fetched
+    /// from the relays, its thread root and its participants are copied,
+    /// so that the notes are published as proper thread replies with
+    /// marked 'e' and 'p' tags (NIP-10).
+    #[arg(long, value_name = "EVENT_ID")]
+    reply_to: Option<String>,
+
+    /// Publish the notes as quote of an event.
+    /// Details:: Used together with '--publish' or '--publish-pow'. Specify the event
+    /// to quote by its Hex id, note or nevent. The event is fetched from
+    /// the relays, a 'mention' tag to the event and a 'p' tag to its author
+    /// are added and a 'nostr:nevent1...' link to the event is appended
+    /// to each note, so that clients display the quoted event.
+    /// Can be combined with '--reply-to'.
+    #[arg(long, value_name = "EVENT_ID")]
+    quote: Option<String>,
+
     /// Publish one or multiple notes with proof-of-work (POW).
     /// Details::
     /// Use also '--pow-difficulty' to specify difficulty.
     /// See also '--publish' to see how shortcut characters
     /// '-' (pipe) and '_' (streamed pipe) are handled.
-    /// Disabled since version nostr-commander-rs 0.2.0 (nostr-sdk 0.21).
+    /// The nonce (NIP-13) is mined locally using all CPU cores. Depending
+    /// on the difficulty this can take from milliseconds to hours. Each
+    /// additional level of difficulty doubles the expected mining time.
     #[arg(long, alias = "pow", value_name = "NOTE", num_args(0..), )]
-    publish_pow: Vec<String>, // ToDo: remove this option
+    publish_pow: Vec<String>,
+
+    /// React to an event.
+    /// Details:: Publishes a reaction (kind 7, NIP-25). The first
+    /// argument is the event to react to, given by its Hex id, note or
+    /// nevent. The optional second argument is the reaction, e.g. an
+    /// emoji. If omitted, '+' (a like) is used. '-' is a dislike.
+    /// E.g. '--react note1SomeEventId "🤙"'. The event is fetched from the
+    /// relays to tag its author, its kind and a relay hint.
+    #[arg(long, value_name = "EVENT_ID+EMOJI", num_args(1..=2), )]
+    react: Vec<String>,
+
+    /// Repost one or multiple events.
+    /// Details:: Publishes a repost (kind 6, NIP-18) for each given
+    /// event. Events are given by their Hex id, note or nevent. The
+    /// events are fetched from the relays to include them, their author
+    /// and a relay hint in the repost.
+    #[arg(long, value_name = "EVENT_ID", num_args(0..), )]
+    repost: Vec<String>,
 
     /// Send one or multiple DMs to one given user.
     /// Details::
@@ -620,9 +943,36 @@ pub struct Args {
     /// or '--dm joe "How about pizza tonight?"'.
     /// See also '--publish' to see how shortcut characters
     /// '-' (pipe) and '_' (streamed pipe) are handled.
+    /// Besides to your own relays, DMs are sent to the inbox relays of
+    /// the recipient, taken from their NIP-65 relay list or the relay of
+    /// the contact. The same holds for the people replied to or mentioned
+    /// in other events.
     #[arg(long, alias = "direct", value_name = "KEY+MSGS", num_args(0..), )]
     dm: Vec<String>,
 
+    /// Specify the protocol used to send DMs.
+    /// Details:: Option 'nip17' sends private DMs that are
+    /// gift-wrapped (NIP-17, NIP-59), they hide sender, recipient
+    /// and time from the relays. This is the default.
+    /// Option 'nip04' sends legacy encrypted DMs (kind 4, NIP-04).
+    /// Use it only for recipients whose clients cannot yet read
+    /// NIP-17 DMs. Both types of DMs are received with '--listen'.
+    #[arg(long, value_enum,
+        value_name = "PROTOCOL",
+        default_value_t = DmProtocol::default(), ignore_case = true, )]
+    dm_protocol: DmProtocol,
+
+    /// Print the DM conversation with one given user.
+    /// Details:: The user is specified via its public key, a
+    /// string in the form of 'npub1...', a Hex key, or an alias from
+    /// one of your contacts. DMs in both directions, NIP-04 as well
+    /// as NIP-17, are fetched from the relays, decrypted and printed
+    /// in chronological order. Use '--limit-number', '--limit-days'
+    /// and '--limit-hours' to restrict the history.
+    /// E.g. '--dm-history joe --limit-days 7 --output json'.
+    #[arg(long, value_name = "KEY")]
+    dm_history: Option<String>,
+
     /// Send one or multiple messages to one given channel.
     /// Details::
     /// The single destination channel is specified via its hash.
@@ -650,10 +1000,22 @@ pub struct Args {
     /// Sampler relay registries are: https://nostr-registry.netlify.app/,
     /// https://nostr.info/, or https://nostr.watch/.
     /// Examples: "wss://relay.damus.io", "wss://nostr.openchain.fr".
-    /// See also '--proxy'.
+    /// See also '--proxy' and '--relay-flags'.
     #[arg(long, value_name = "RELAY_URI", num_args(0..), )]
     add_relay: Vec<Url>,
 
+    /// Specify how the relays added with --add-relay are used.
+    /// Details:: Used by --add-relay. A list of 'read', 'write',
+    /// 'inbox' and 'outbox', e.g. '--relay-flags read inbox' or
+    /// '--relay-flags write,outbox'. Events are fetched and subscribed
+    /// from 'read' relays and published to 'write' relays. 'inbox' and
+    /// 'outbox' mark the relays that others should use to send events
+    /// to you and to find your events (NIP-65). By default a relay has
+    /// all four flags. Adding a relay that exists already replaces its
+    /// flags. The flags are stored in the credentials file.
+    #[arg(long, value_name = "FLAGS", num_args(1..), value_delimiter = ',', )]
+    relay_flags: Vec<RelayFlag>,
+
     /// Specify a proxy for relays.
     /// Details:: Used by --add-relay.
     /// Note that this proxy will be applied to all of the relays specified
@@ -666,36 +1028,113 @@ pub struct Args {
     /// and from all relays.
     /// A possible relay that you can use together with a Tor proxy is
     /// "ws://jgqaglhautb4k6e6i2g34jakxiemqp6z4wynlirltuukgkft2xuglmqd.onion".
+    /// See also '--proxy-user' and '--proxy-all'.
     #[arg(long)]
     proxy: Option<SocketAddr>,
 
+    /// Specify the SOCKS5 username and password of the proxy.
+    /// Details:: Used by --proxy and --proxy-all, e.g.
+    /// '--proxy-user "joe:secret"'. The username and password are stored
+    /// with the relays in the credentials file. With Tor, different
+    /// usernames get different circuits. Only supported on Linux.
+    #[arg(long, value_name = "USER:PASSWORD")]
+    proxy_user: Option<String>,
+
+    /// Connect to all relays through this proxy.
+    /// Details:: Overrides the proxies stored with the relays for this
+    /// run. No direct connection is opened, all relays are connected
+    /// through the given SOCKS5 proxy, e.g. Tor at "127.0.0.1:9050".
+    /// Options that would connect directly, like '--readme' or
+    /// '--version check', are refused. Use '--proxy-user' if the
+    /// proxy requires a username and password. The inbox relays of
+    /// recipients are connected through the proxy as well. Without
+    /// '--proxy-all' they are skipped if any of your relays uses a proxy.
+    #[arg(long, value_name = "PROXY")]
+    proxy_all: Option<SocketAddr>,
+
     /// Remove one or multiple relays from local config file.
     /// Details:: See --add-relay.
     #[arg(long, value_name = "RELAY_URI", num_args(0..), )]
     remove_relay: Vec<Url>,
 
-    // todo tag
+    /// Check the health of all relays.
+    /// Details:: Every relay of the credentials file is connected to
+    /// on its own. For each relay the time to connect, the NIP-11
+    /// relay information (name, supported NIPs, limitations, payment
+    /// requirements), whether it accepts a test event and whether
+    /// it returns your latest text note are reported. The test event
+    /// is ephemeral (kind 20000+), relays do not store it. Each step
+    /// waits at most '--fetch-timeout' seconds. Afterwards you are
+    /// offered to remove the relays that could not be connected from
+    /// the credentials file, use '--yes' to remove them without asking.
+    #[arg(long, default_value_t = false)]
+    check_relays: bool,
+
+    /// Publish your relays as relay list (NIP-65, kind 10002).
+    /// Details:: The relays of the credentials file are published
+    /// so that other clients can find them. Relays with the 'inbox'
+    /// flag are marked 'read', relays with the 'outbox' flag are
+    /// marked 'write', relays with both flags are not marked. Relays
+    /// with neither flag are not published. See '--relay-flags'.
+    #[arg(long, default_value_t = false)]
+    publish_relay_list: bool,
+
+    /// Import the relay list (NIP-65, kind 10002) of a user.
+    /// Details:: The user is specified via its public key, a string
+    /// in the form of 'npub1...', a Hex key, or an alias from one of
+    /// your contacts. The newest relay list of the user is fetched and
+    /// its relays are added to the credentials file. 'read' relays
+    /// get the flags 'read' and 'inbox', 'write' relays get the flags
+    /// 'write' and 'outbox', unmarked relays get all flags. Relays
+    /// you have already are kept as they are. '--proxy' and
+    /// '--proxy-user' apply to the imported relays. Use your own
+    /// public key to copy your relay setup from another client.
+    #[arg(long, value_name = "KEY")]
+    import_relay_list: Option<String>,
+
     /// Specify one or multiple tags to attach to notes or DMs.
-    /// Details:: Not yet implemented.
-    #[arg(long)]
+    /// Details:: The tags are attached to every note, DM and channel
+    /// message sent in this run. A tag is specified as
+    /// 'letter:value', e.g. 't:nostr' for a hashtag, 'p:joe' for
+    /// mentioning a person by contact alias, npub or Hex key, or
+    /// 'e:note1SomeEventId' for referencing an event by Hex id, note
+    /// or nevent. Any other tag can be given as raw JSON array, e.g.
+    /// '["r","https://example.com"]'. E.g.
+    /// --publish "Hello" --tag "t:nostr" "p:joe".
+    #[arg(long, value_name = "TAG", num_args(0..), )]
     tag: Vec<String>,
 
     /// Display current metadata.
-    /// Details:: shows data in your config file.
-    #[arg(long, default_value_t = false)]
-    show_metadata: bool,
+    /// Details:: Shows the metadata in your config file.
+    /// If used without an argument such as '--show-metadata' it will
+    /// only print the local metadata. If 'remote' is added
+    /// ('--show-metadata remote') then additionally your latest
+    /// metadata event (kind 0) is fetched from the relays and compared
+    /// field-by-field with the local metadata. This shows if another
+    /// client has overwritten your profile. Use '--change-metadata'
+    /// without any other metadata option to publish the local
+    /// metadata again.
+    #[arg(long, value_name = "REMOTE")]
+    show_metadata: Option<Option<ShowMetadata>>,
 
     /// Modify existing metadata of the user.
     /// Details::
     /// Use this option in combination with --name,
     ///  --display_name, --about, --picture, and --nip05.
+    /// Only the provided fields are changed, all others are kept.
+    /// To clear a field provide an empty string '' (or 'none:' for
+    /// --picture). The updated metadata is stored in the credentials
+    /// file and published as metadata event (kind 0) to all relays.
     #[arg(long, default_value_t = false)]
     change_metadata: bool,
 
     /// Specify optional proof-of-work (POW) difficulty.
     /// Details::
     /// Use with '--publish_pow' to specify difficulty.
-    /// If not specified the default will be used.
+    /// The difficulty is the number of leading zero bits of the
+    /// event id. If not specified the default will be used.
+    /// Each additional bit doubles the mining time, the highest
+    /// difficulty is 32.
     #[arg(long, value_name = "DIFFICULTY", default_value_t = POW_DIFFICULTY_DEFAULT, )]
     pow_difficulty: u8,
 
@@ -732,13 +1171,18 @@ pub struct Args {
     /// extra data items added for convenience. In most cases
     /// the output will be processed by other programs rather
     /// than read by humans. Option 'json-max' is practically
-    /// the same as 'json', but yet another additional field
-    /// is added.
+    /// the same as 'json', but received events additionally
+    /// carry the full signed event, the relay URL, the
+    /// subscription id and the contact aliases of all keys
+    /// in 'p' tags.
     /// In most cases the output will
     /// be processed by other programs rather than read by
     /// humans. Option 'json-spec' only prints information
-    /// that adheres 1-to-1 to the Nostr Specification.
-    /// Currently this type is not supported.
+    /// that adheres 1-to-1 to the Nostr Specification, one JSON
+    /// item per line: received relay messages such as '["EVENT",
+    /// ...]' with '--listen', '["OK", ...]' of each relay for every
+    /// published event, raw NIP-01 event objects with '--fetch'. This output can be piped into
+    /// other Nostr tools.
     /// If no data is available that corresponds exactly with
     /// the Nostr Specification, no data will be printed.
     #[arg(short, long, value_enum,
@@ -753,10 +1197,103 @@ pub struct Args {
     /// to get the event ids for published notices.
     /// Subscriptions do not automatically turn listening on.
     /// If you want to listen to your subscriptions, you must use
-    /// --listen.
+    /// --listen. While listening, DMs addressed to you (NIP-04 and
+    /// NIP-17) are received, decrypted and printed.
     #[arg(short, long, default_value_t = false)]
     listen: bool,
 
+    /// Fetch stored events of your subscriptions once, then exit.
+    /// Details:: Like '--listen' the subscribed keys, authors,
+    /// channels and filters are requested from the relays, honouring
+    /// '--limit-number', '--limit-days', etc. DMs addressed to you are
+    /// only fetched with '--fetch-dms'.
+    /// All stored events are printed in chronological order. Unlike
+    /// '--listen' the program terminates once all relays have sent
+    /// all their stored events or once '--fetch-timeout' expires.
+    /// Useful in scripts and cron jobs.
+    #[arg(long, default_value_t = false)]
+    fetch: bool,
+
+    /// Fetch the DMs addressed to you once, then exit.
+    /// Details:: Like '--fetch', but the DMs (NIP-04 and NIP-17)
+    /// addressed to you are fetched, decrypted and printed as well.
+    /// Implies '--fetch'.
+    #[arg(long, default_value_t = false)]
+    fetch_dms: bool,
+
+    /// Keep events in a local event store.
+    /// Details:: Events that are sent as well as events that are
+    /// received with '--listen', '--fetch' and so on are stored in
+    /// the file 'events.jsonl' next to the credentials file. Each
+    /// event is stored only once, even if several relays deliver
+    /// it. With a store, '--listen' and '--fetch' first print the
+    /// matching stored events. Once a subscription was received
+    /// completely from all relays, later runs only request events
+    /// newer than that from the relays. The synced subscriptions
+    /// are recorded in 'events-synced.json'. Subscriptions limited
+    /// with '--limit-number' are not recorded. The store grows with
+    /// every new event, it is never compacted and it is read
+    /// completely on every run. Delete both files to start over.
+    /// See also '--query'.
+    #[arg(long, default_value_t = false)]
+    store: bool,
+
+    /// Query the local event store, without contacting relays.
+    /// Details:: Each query is a NIP-01 filter in JSON format, see
+    /// '--filter', e.g. '{"authors":["npub1SomeKey"],"kinds":[1]}'
+    /// to select events by author, kind, tag ('#t', '#p', '#e')
+    /// and time range ('since', 'until'). '--limit-number',
+    /// '--limit-days', etc. are honoured as well. Matching events
+    /// are printed in chronological order. The store is filled
+    /// with '--store'.
+    #[arg(long, value_name = "JSON", num_args(0..), )]
+    query: Vec<String>,
+
+    /// Retry all events in the outbox now.
+    /// Details:: Events that were not accepted by all relays are kept
+    /// in the outbox, the file 'outbox.json' next to the credentials
+    /// file. On every run that connects to the relays, the events in
+    /// the outbox are retried with backoff: first after a minute,
+    /// then waiting twice as long after each attempt, up to a day.
+    /// This option retries all of them immediately.
+    /// See also '--show-outbox'.
+    #[arg(long, default_value_t = false)]
+    flush_outbox: bool,
+
+    /// Show the events in the outbox.
+    /// Details:: For each event in the outbox, the relays that have
+    /// and have not acknowledged it are listed, together with the
+    /// message a relay gave when rejecting it.
+    /// See also '--flush-outbox'.
+    #[arg(long, default_value_t = false)]
+    show_outbox: bool,
+
+    /// Wait for the relays to acknowledge the published events.
+    /// Details:: Connecting to the relays and sending each event waits
+    /// at most the given number of seconds, 20 by default, e.g.
+    /// '--wait-ok' or '--wait-ok 60'. Once all relays have accepted
+    /// or rejected the events, or the timeout expired, a report
+    /// is printed listing for each event the status on each relay.
+    /// If any event was accepted by fewer relays than '--quorum'
+    /// the program exits with a nonzero exit code. This applies to
+    /// '--publish', '--publish-pow', '--dm', '--send-channel-message',
+    /// '--react' and '--repost'.
+    #[arg(long, value_name = "SECONDS")]
+    wait_ok: Option<Option<u64>>,
+
+    /// Specify how many relays must accept each event for '--wait-ok'.
+    /// Details:: By default it suffices that one relay accepts an event.
+    #[arg(long, value_name = "RELAYS", default_value_t = QUORUM_DEFAULT, )]
+    quorum: usize,
+
+    /// Specify the timeout for fetching events from relays in seconds.
+    /// Details:: Applies to '--fetch' as well as to all other options
+    /// that fetch stored events, e.g. '--show-metadata' or
+    /// '--dm-history'. Relays that have not delivered all their stored
+    /// events by then are ignored.
+    #[arg(long, value_name = "SECONDS", default_value_t = FETCH_TIMEOUT_DEFAULT, )]
+    fetch_timeout: u64,
+
     /// Add one or more contacts.
     /// Details:: Must be used in combination with
     /// --alias, --key, --relay. If you want to add N new contacts,
@@ -765,6 +1302,8 @@ pub struct Args {
     /// --key npub1JanesPublicKey npub1JoesPublicKey
     /// --relay "wss://janes.relay.org" "wss://joes.relay.org".
     /// Aliases must be unique. Alias can be seen as a nickname.
+    /// Contacts are only changed locally, use '--publish-contacts'
+    /// to publish them.
     #[arg(long, default_value_t = false)]
     add_contact: bool,
 
@@ -780,6 +1319,26 @@ pub struct Args {
     #[arg(long, default_value_t = false)]
     show_contacts: bool,
 
+    /// Merge the contact list published on the relays into your contacts.
+    /// Details:: Fetches your contact list (kind 3, NIP-02), e.g. as
+    /// published by another client, and adds the contacts you do not
+    /// have yet. Your aliases and relays are kept, missing ones are
+    /// taken from the published list. Contacts you removed with
+    /// '--remove-contact' since the last sync or publish are not added
+    /// again, contacts removed by another client are removed from
+    /// your contacts. Nothing is published, see '--publish-contacts'.
+    /// The added contacts are printed.
+    #[arg(long, default_value_t = false)]
+    sync_contacts: bool,
+
+    /// Publish your contacts as your contact list.
+    /// Details:: The published contact list (kind 3, NIP-02) replaces
+    /// the one on the relays. So it is merged into your contacts first,
+    /// as with '--sync-contacts', and nothing is published if it cannot
+    /// be fetched. Contacts removed with '--remove-contact' stay removed.
+    #[arg(long, default_value_t = false)]
+    publish_contacts: bool,
+
     /// Provide one or multiple aliases (nicknames).
     /// Details:: This is used in combination with arguments
     /// --add-contact and --remove-contact.
@@ -867,6 +1426,33 @@ pub struct Args {
     #[arg(long, value_name = "KEY", num_args(0..), )]
     unsubscribe_channel: Vec<String>,
 
+    /// Use one or more raw Nostr filters for this run.
+    /// Details:: Each filter is a NIP-01 filter in JSON format, e.g.
+    /// '{"kinds":[1],"#t":["nostr"],"limit":10}'. Fields 'ids',
+    /// 'authors', 'kinds', '#e', '#p', '#t' (and other tags),
+    /// 'since', 'until' and 'limit' are supported. The filters are
+    /// used by '--listen' and '--fetch' in addition to the
+    /// subscriptions. They are not stored, see '--subscribe-filter'.
+    #[arg(long, value_name = "JSON", num_args(0..), )]
+    filter: Vec<String>,
+
+    /// Subscribe to one or more named raw Nostr filters.
+    /// Details:: Each argument has the form 'NAME=JSON', e.g.
+    /// 'nostr-tag={"kinds":[1],"#t":["nostr"]}'. See '--filter' for
+    /// the JSON format. The filters are stored in the credentials
+    /// file and used by '--listen' and '--fetch' like the other
+    /// subscriptions. Subscribing to an existing name replaces that
+    /// filter.
+    #[arg(long, value_name = "NAME=JSON", num_args(0..), )]
+    subscribe_filter: Vec<String>,
+
+    /// Unsubscribe from named filters.
+    /// Details:: Removes one or multiple filters by name from the
+    /// filter subscription list.
+    /// See --subscribe-filter.
+    #[arg(long, value_name = "NAME", num_args(0..), )]
+    unsubscribe_filter: Vec<String>,
+
     /// Limit the number of past messages to receive when subscribing.
     /// Details:: By default there is no limit (0), i.e. all old messages
     /// available to the relay will be received.
@@ -908,6 +1494,7 @@ impl Args {
     pub fn new() -> Args {
         Args {
             creds: Credentials::new(),
+            bridges: Socks5Bridges::default(),
             usage: false,
             help: false,
             manual: false,
@@ -921,7 +1508,8 @@ impl Args {
             // credentials file path
             credentials: get_credentials_default_path(),
             create_user: false,
-            delete_user: false,
+            delete_user: None,
+            yes: false,
             name: None,
             display_name: None,
             about: None,
@@ -929,24 +1517,47 @@ impl Args {
             nip05: None,
             nsec: None,
             publish: Vec::new(),
+            reply_to: None,
+            quote: None,
             publish_pow: Vec::new(),
+            react: Vec::new(),
+            repost: Vec::new(),
             dm: Vec::new(),
+            dm_protocol: DmProtocol::default(),
+            dm_history: None,
             send_channel_message: Vec::new(),
             add_relay: Vec::new(),
+            relay_flags: Vec::new(),
             remove_relay: Vec::new(),
+            check_relays: false,
+            publish_relay_list: false,
+            import_relay_list: None,
             tag: Vec::new(),
-            show_metadata: false,
+            show_metadata: None,
             change_metadata: false,
             pow_difficulty: POW_DIFFICULTY_DEFAULT,
             proxy: None,
+            proxy_user: None,
+            proxy_all: None,
             show_public_key: false,
             show_secret_key: false,
             whoami: false,
             output: Output::default(),
             listen: false,
+            fetch: false,
+            fetch_dms: false,
+            fetch_timeout: FETCH_TIMEOUT_DEFAULT,
+            store: false,
+            query: Vec::new(),
+            flush_outbox: false,
+            show_outbox: false,
+            wait_ok: None,
+            quorum: QUORUM_DEFAULT,
             add_contact: false,
             remove_contact: false,
             show_contacts: false,
+            sync_contacts: false,
+            publish_contacts: false,
             alias: Vec::new(),
             key: Vec::new(),
             relay: Vec::new(),
@@ -957,6 +1568,9 @@ impl Args {
             subscribe_channel: Vec::new(),
             unsubscribe_pubkey: Vec::new(),
             unsubscribe_author: Vec::new(),
+            filter: Vec::new(),
+            subscribe_filter: Vec::new(),
+            unsubscribe_filter: Vec::new(),
             unsubscribe_channel: Vec::new(),
             limit_number: 0,
             limit_days: 0,
@@ -974,403 +1588,1768 @@ impl Args {
 pub struct Relay {
     url: Url,
     proxy: Option<SocketAddr>,
+    // relays stored before the flags existed are used for everything
+    #[serde(default = "default_true")]
+    read: bool,
+    #[serde(default = "default_true")]
+    write: bool,
+    #[serde(default = "default_true")]
+    inbox: bool,
+    #[serde(default = "default_true")]
+    outbox: bool,
+    #[serde(default)]
+    proxy_auth: Option<ProxyAuth>,
 }
 
-impl AsRef<Relay> for Relay {
-    fn as_ref(&self) -> &Self {
-        self
-    }
-}
-
-impl Default for Relay {
-    fn default() -> Self {
-        Self::new(Url::parse("wss://relay.nostr.info/").unwrap(), None)
-    }
+/// SOCKS5 username and password of a proxy
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProxyAuth {
+    username: String,
+    password: String,
 }
 
-/// implementation of Relay struct
-impl Relay {
-    /// Default constructor
-    fn new(url: Url, proxy: Option<SocketAddr>) -> Self {
-        Self { url, proxy }
+/// Converting from String "user:password" to ProxyAuth for --proxy-user option
+impl FromStr for ProxyAuth {
+    type Err = Error;
+    fn from_str(src: &str) -> Result<ProxyAuth, Error> {
+        match src.split_once(':') {
+            Some((username, password)) if !username.is_empty() => Ok(ProxyAuth {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            }),
+            _ => {
+                error!("Error: proxy user must look like 'user:password'.");
+                Err(Error::InvalidProxy)
+            }
+        }
     }
 }
 
-/// A struct for the credentials. These will be serialized into JSON
-/// and written to the credentials.json file for permanent storage and
-/// future access.
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Credentials {
-    secret_key_bech32: String, // nsec1...// private_key
-    public_key_bech32: String, // npub1...
-    relays: Vec<Relay>,
-    metadata: Metadata,
-    contacts: Vec<Contact>,
-    subscribed_pubkeys: Vec<PublicKey>,
-    subscribed_authors: Vec<PublicKey>,
-    // todo: zzz subscribed_channels should be EventId's ?
-    subscribed_channels: Vec<PublicKey>,
+/// Default for boolean fields that are missing in older files
+fn default_true() -> bool {
+    true
 }
 
-impl AsRef<Credentials> for Credentials {
+impl AsRef<Relay> for Relay {
     fn as_ref(&self) -> &Self {
         self
     }
 }
 
-impl Default for Credentials {
+impl Default for Relay {
     fn default() -> Self {
-        Self::new()
+        Self::new(Url::parse("wss://relay.nostr.info/").unwrap(), None)
     }
 }
 
-/// implementation of Credentials struct
-impl Credentials {
-    /// Default constructor
-    fn new() -> Self {
+/// implementation of Relay struct
+impl Relay {
+    /// Default constructor, the relay is used for everything
+    fn new(url: Url, proxy: Option<SocketAddr>) -> Self {
         Self {
-            secret_key_bech32: "".to_owned(),
-            public_key_bech32: "".to_owned(),
-            relays: Vec::new(),
-            metadata: Metadata::new(),
-            contacts: Vec::new(),
-            subscribed_pubkeys: Vec::new(),
-            subscribed_authors: Vec::new(),
-            subscribed_channels: Vec::new(),
+            url,
+            proxy,
+            read: true,
+            write: true,
+            inbox: true,
+            outbox: true,
+            proxy_auth: None,
         }
     }
 
-    // /// Default constructor
-    // fn create(
-    //     secret_key_bech32: String,
-    //     public_key_bech32: String,
-    //     relays: Vec<Url>,
-    //     metadata: Metadata,
-    // ) -> Self {
-    //     Self {
-    //         secret_key_bech32,
-    //         public_key_bech32,
-    //         relays,
-    //         metadata,
-    //     }
-    // }
+    /// Set the username and password of the proxy
+    fn with_proxy_auth(mut self, proxy_auth: Option<ProxyAuth>) -> Self {
+        self.proxy_auth = proxy_auth;
+        self
+    }
 
-    /// Constructor for Credentials
-    fn load(path: &Path) -> Result<Credentials, Error> {
-        let reader = File::open(path)?;
-        Credentials::set_permissions(&reader)?;
-        let credentials: Credentials = serde_json::from_reader(reader)?;
-        let mut credentialsfiltered = credentials.clone();
-        credentialsfiltered.secret_key_bech32 = "***".to_string();
-        info!("loaded credentials are: {:?}", credentialsfiltered);
-        Ok(credentials)
+    /// Restrict the relay to the given flags, no flags means all flags
+    fn with_flags(mut self, flags: &[RelayFlag]) -> Self {
+        if !flags.is_empty() {
+            self.read = flags.contains(&RelayFlag::Read);
+            self.write = flags.contains(&RelayFlag::Write);
+            self.inbox = flags.contains(&RelayFlag::Inbox);
+            self.outbox = flags.contains(&RelayFlag::Outbox);
+        }
+        self
     }
 
-    /// Writing the credentials to a file
-    fn save(&self, path: &Path) -> Result<(), Error> {
-        fs::create_dir_all(path.parent().ok_or(Error::NoHomeDirectory)?)?;
-        let writer = File::create(path)?;
-        serde_json::to_writer_pretty(&writer, self)?;
-        Credentials::set_permissions(&writer)?;
-        Ok(())
+    /// The NIP-65 marker of the relay, None if it is neither inbox nor outbox
+    fn relay_metadata(&self) -> Option<Option<RelayMetadata>> {
+        match (self.inbox, self.outbox) {
+            (true, true) => Some(None),
+            (true, false) => Some(Some(RelayMetadata::Read)),
+            (false, true) => Some(Some(RelayMetadata::Write)),
+            (false, false) => None,
+        }
     }
 
-    #[cfg(unix)]
-    fn set_permissions(file: &File) -> Result<(), Error> {
-        use std::os::unix::fs::PermissionsExt;
-        let perms = file.metadata()?.permissions();
-        // is the file world-readable? if so, reset the permissions to 600
-        if perms.mode() & 0o4 == 0o4 {
-            file.set_permissions(fs::Permissions::from_mode(0o600))
-                .unwrap();
+    /// A relay from a NIP-65 relay list entry
+    fn from_relay_metadata(url: Url, metadata: Option<RelayMetadata>) -> Self {
+        let relay = Self::new(url, None);
+        match metadata {
+            None => relay,
+            Some(RelayMetadata::Read) => relay.with_flags(&[RelayFlag::Read, RelayFlag::Inbox]),
+            Some(RelayMetadata::Write) => relay.with_flags(&[RelayFlag::Write, RelayFlag::Outbox]),
         }
-        Ok(())
     }
 
-    #[cfg(not(unix))]
-    fn set_permissions(file: &File) -> Result<(), Error> {
-        Ok(())
+    /// Relay options for the client, carrying the flags of the relay
+    fn options(&self) -> RelayOptions {
+        let mut flags = RelayServiceFlags::PING;
+        for (set, flag) in [
+            (self.read, RelayServiceFlags::READ),
+            (self.write, RelayServiceFlags::WRITE),
+            (self.inbox, RelayServiceFlags::INBOX),
+            (self.outbox, RelayServiceFlags::OUTBOX),
+        ] {
+            if set {
+                flags.add(flag);
+            }
+        }
+        RelayOptions::new().flags(flags)
     }
 }
 
-/// Gets the *default* path (including file name) of the credentials file
-/// The default path might not be the actual path as it can be overwritten with command line
-/// options.
-fn get_credentials_default_path() -> PathBuf {
-    let dir = ProjectDirs::from_path(PathBuf::from(get_prog_without_ext())).unwrap();
-    // fs::create_dir_all(dir.data_dir());
-    let dp = dir.data_dir().join(CREDENTIALS_FILE_DEFAULT);
-    debug!(
-        "Data will be put into project directory {:?}.",
-        dir.data_dir()
-    );
-    info!("Credentials file with private key is {}.", dp.display());
-    dp
+/// A struct for named filters. These will be serialized into JSON
+/// and written to the credentials.json file for permanent storage and
+/// future access.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NamedFilter {
+    name: String,
+    filter: Filter,
 }
 
-/// Gets the *actual* path (including file name) of the credentials file
-/// The default path might not be the actual path as it can be overwritten with command line
-/// options.
-fn get_credentials_actual_path(ap: &Args) -> &PathBuf {
-    &ap.credentials
+/// The time range of a filter for which all events were received
+/// from the relays and stored. `since` of `None` means from the beginning.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyncedRange {
+    since: Option<Timestamp>,
+    until: Timestamp,
 }
 
-/// Return true if credentials file exists, false otherwise
-fn credentials_exist(ap: &Args) -> bool {
-    let dp = get_credentials_default_path();
-    let ap = get_credentials_actual_path(ap);
-    debug!(
-        "credentials_default_path = {:?}, credentials_actual_path = {:?}",
-        dp, ap
-    );
-    let exists = ap.is_file();
-    if exists {
-        debug!("{:?} exists and is file. Not sure if readable though.", ap);
-    } else {
-        debug!("{:?} does not exist or is not a file.", ap);
+/// implementation of SyncedRange struct
+impl SyncedRange {
+    /// Whether the range reaches back to `since`
+    fn covers(&self, since: Option<Timestamp>) -> bool {
+        match (self.since, since) {
+            (None, _) => true,
+            (Some(synced), Some(since)) => synced <= since,
+            (Some(_), None) => false,
+        }
     }
-    exists
-}
-
-/// Gets version number, static if available, otherwise default.
-fn get_version() -> &'static str {
-    VERSION_O.unwrap_or(VERSION)
 }
 
-/// Gets Rust package name, static if available, otherwise default.
-fn get_pkg_name() -> &'static str {
-    PKG_NAME_O.unwrap_or(PKG_NAME)
+/// A local store of events. The events are written as JSON lines to
+/// a file next to the credentials file. Each event is stored once, no
+/// matter how many relays deliver it. The file is only appended to, it is
+/// never compacted and is read completely on every run.
+pub struct EventStore {
+    path: PathBuf,
+    events: Vec<nostr_sdk::Event>,
+    ids: HashSet<EventId>,
+    /// synced ranges by filter, see filter_key()
+    synced: HashMap<String, SyncedRange>,
 }
 
-/// Gets Rust binary name, static if available, otherwise default.
-fn get_bin_name() -> &'static str {
-    BIN_NAME_O.unwrap_or(BIN_NAME)
+/// implementation of EventStore struct
+impl EventStore {
+    /// Load the store from file. A missing file is an empty store.
+    fn open(path: &Path) -> Result<Self, Error> {
+        let mut store = Self {
+            path: path.to_path_buf(),
+            events: Vec::new(),
+            ids: HashSet::new(),
+            synced: HashMap::new(),
+        };
+        let sync_path = store.sync_path();
+        if sync_path.is_file() {
+            match serde_json::from_str(&fs::read_to_string(&sync_path)?) {
+                Ok(synced) => store.synced = synced,
+                Err(ref e) => warn!(
+                    "Ignoring invalid sync file {:?}. Reported error {:?}.",
+                    sync_path, e
+                ),
+            }
+        }
+        if path.is_file() {
+            let content = fs::read_to_string(path)?;
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                match nostr_sdk::Event::from_json(line) {
+                    Ok(event) => {
+                        if store.ids.insert(event.id) {
+                            store.events.push(event);
+                        }
+                    }
+                    Err(ref e) => warn!(
+                        "Skipping invalid line in event store {:?}. Reported error {:?}.",
+                        path, e
+                    ),
+                }
+            }
+        }
+        debug!(
+            "Event store {:?} holds {} events.",
+            path,
+            store.events.len()
+        );
+        Ok(store)
+    }
+
+    /// Add events that are not stored yet, append them to the file.
+    /// Returns the number of new events.
+    fn add(&mut self, events: &[nostr_sdk::Event]) -> Result<usize, Error> {
+        let mut lines = String::new();
+        let mut num = 0usize;
+        for event in events {
+            if self.ids.insert(event.id) {
+                lines += &event.as_json();
+                lines += "\n";
+                self.events.push(event.clone());
+                num += 1;
+            }
+        }
+        if num != 0 {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            Credentials::set_permissions(&file)?;
+            file.write_all(lines.as_bytes())?;
+        }
+        Ok(num)
+    }
+
+    /// Stored events matching any of the filters, in chronological order.
+    /// Like on relays, the limit of a filter keeps the newest events.
+    fn query(&self, filters: &[Filter]) -> Vec<nostr_sdk::Event> {
+        let mut ids: HashSet<EventId> = HashSet::new();
+        let mut result: Vec<nostr_sdk::Event> = Vec::new();
+        for filter in filters {
+            let mut matches: Vec<&nostr_sdk::Event> = self
+                .events
+                .iter()
+                .filter(|e| filter.match_event(e))
+                .collect();
+            matches.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+            if let Some(limit) = filter.limit {
+                matches.truncate(limit);
+            }
+            for event in matches {
+                if ids.insert(event.id) {
+                    result.push(event.clone());
+                }
+            }
+        }
+        result.sort_by_key(|e| e.created_at);
+        result
+    }
+
+    /// Stored events matching the filters, and the filters changed to only
+    /// request events from the relays that are newer than the synced range
+    /// of the filter. Filters that were never synced are not changed.
+    fn catch_up(&self, filters: Vec<Filter>) -> (Vec<nostr_sdk::Event>, Vec<Filter>) {
+        let stored = self.query(&filters);
+        let filters = filters
+            .into_iter()
+            .map(|filter| match self.synced.get(&filter_key(&filter)) {
+                Some(range) if range.covers(filter.since) => {
+                    let mut until = range.until;
+                    // gift wraps are backdated, newer ones can look older
+                    if filter
+                        .kinds
+                        .as_ref()
+                        .is_some_and(|k| k.contains(&Kind::GiftWrap))
+                    {
+                        until = until - Duration::from_secs(GIFT_WRAP_TWEAK);
+                    }
+                    if filter.since.is_none_or(|since| since < until) {
+                        filter.since(until)
+                    } else {
+                        filter
+                    }
+                }
+                _ => filter,
+            })
+            .collect();
+        (stored, filters)
+    }
+
+    /// Record that all events matching the filter up to `until` were received
+    /// and stored. A range continuing the recorded range extends it.
+    /// Filters with a limit are not recorded, the relays might have more events.
+    fn mark_synced(&mut self, filter: &Filter, until: Timestamp) -> Result<(), Error> {
+        if filter.limit.is_some() {
+            return Ok(());
+        }
+        let key = filter_key(filter);
+        let since = match self.synced.get(&key) {
+            Some(range)
+                if range.covers(filter.since)
+                    && filter.since.is_some_and(|since| since <= range.until) =>
+            {
+                range.since
+            }
+            _ => filter.since,
+        };
+        self.synced.insert(key, SyncedRange { since, until });
+        let sync_path = self.sync_path();
+        if let Some(dir) = sync_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut writer = File::create(sync_path)?;
+        Credentials::set_permissions(&writer)?;
+        writer.write_all(serde_json::to_string_pretty(&self.synced)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// The sync file is kept next to the event store file
+    fn sync_path(&self) -> PathBuf {
+        self.path.with_file_name(EVENT_SYNC_FILE_DEFAULT)
+    }
 }
 
-/// Gets Rust package repository, static if available, otherwise default.
-fn get_pkg_repository() -> &'static str {
-    PKG_REPOSITORY_O.unwrap_or(PKG_REPOSITORY)
+/// Identifies a filter independent of its time range and limit
+fn filter_key(filter: &Filter) -> String {
+    let mut filter = filter.clone();
+    filter.since = None;
+    filter.until = None;
+    filter.limit = None;
+    filter.as_json()
 }
 
-/// Gets program name without extension.
-fn get_prog_without_ext() -> &'static str {
-    get_bin_name() // with -rs suffix
-                   // get_pkg_name() // without -rs suffix
+/// Gets the path of the local event store, next to the credentials file
+fn get_event_store_path(ap: &Args) -> PathBuf {
+    get_credentials_actual_path(ap).with_file_name(EVENT_STORE_FILE_DEFAULT)
 }
 
-/// Prints the usage info
-pub fn usage() {
-    let help_str = Args::command().render_usage().to_string();
-    println!("{}", &help_str);
-    println!("Options:");
-    // let cmd = Args::command();
-    // // println!("{:?}", cmd);
-    // // for arg in cmd.get_arguments() {
-    // //         println!("{:?}",arg);
-    // // }
-    // // for arg in cmd.get_arguments() {
-    // //         println!("{}",arg); // bug in clap, panics
-    // // }
-    // for arg in cmd.get_arguments() {
-    //     let s = arg.get_help().unwrap().to_string();
-    //     let v: Vec<&str> = s.split("Details::").collect();
-    //     let val_names = arg.get_value_names().unwrap_or(&[]);
-    //     let mut pvalnames = false;
-    //     match arg.get_num_args() {
-    //         None => {}
-    //         Some(range) => {
-    //             println!("range {:?}", range);
-    //             if range != clap::builder::ValueRange::EMPTY {
-    //                 pvalnames = true;
-    //             }
-    //         }
-    //     }
-    //     if pvalnames {
-    //         println!(
-    //             "--{} [<{}>]:  {}",
-    //             arg.get_long().unwrap(),
-    //             val_names[0],
-    //             v[0]
-    //         );
-    //     } else {
-    //         println!("--{}: {}", arg.get_long().unwrap(), v[0]);
-    //     }
-    // }
-    let help_str = Args::command().render_help().to_string();
-    let v: Vec<&str> = help_str.split('\n').collect();
-    for l in v {
-        if l.starts_with("  -") || l.starts_with("      --") {
-            println!("{}", &l);
-        }
-    }
+/// Gets the path of the sync file of the local event store
+fn get_event_sync_path(ap: &Args) -> PathBuf {
+    get_credentials_actual_path(ap).with_file_name(EVENT_SYNC_FILE_DEFAULT)
 }
 
-/// Prints the short help
-pub fn help() {
-    let help_str = Args::command().render_help().to_string();
-    // println!("{}", &help_str);
-    // regex to remove shortest pieces "Details:: ... \n  -"
-    // regex to remove shortest pieces "Details:: ... \n      --"
-    // regex to remove shortest pieces "Details:: ... \nPS:"
-    // 2 regex groups: delete and keep.
-    // [\S\s]*? ... match anything in a non-greedy fashion
-    // stop when either "PS:", "  -" or "      --" is reached
-    let re = Regex::new(r"(?P<del>[ ]+Details::[\S\s]*?)(?P<keep>\nPS:|\n  -|\n      --)").unwrap();
-    let after = re.replace_all(&help_str, "$keep");
-    print!("{}", &after.replace("\n\n", "\n")); // remove empty lines
-    println!("{}", "Use --manual to get more detailed help information.");
+/// The status of an event in the outbox on one relay.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RelayAck {
+    url: Url,
+    accepted: bool,
+    message: Option<String>,
 }
 
-/// Prints the long help
-pub fn manual() {
-    let help_str = Args::command().render_long_help().to_string();
-    println!("{}", &help_str);
+/// An event in the outbox, i.e. an event not accepted by all relays yet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    event: nostr_sdk::Event,
+    relays: Vec<RelayAck>,
+    attempts: u32,
+    next_attempt: Timestamp,
 }
 
-/// Prints the README.md file
-pub async fn readme() {
-    match reqwest::get(URL_README).await {
-        Ok(resp) => {
-            debug!("Got README.md file from URL {:?}.", URL_README);
-            println!("{}", resp.text().await.unwrap())
+/// implementation of OutboxEntry struct
+impl OutboxEntry {
+    /// Default constructor, no relay has accepted the event yet
+    fn new(event: nostr_sdk::Event, urls: &[Url]) -> Self {
+        Self {
+            event,
+            relays: urls
+                .iter()
+                .map(|url| RelayAck {
+                    url: url.clone(),
+                    accepted: false,
+                    message: None,
+                })
+                .collect(),
+            attempts: 0,
+            next_attempt: Timestamp::now(),
         }
-        Err(ref e) => {
-            println!(
-                "Error getting README.md from {:#?}. Reported error {:?}.",
-                URL_README, e
-            );
+    }
+
+    /// Relays that have not accepted the event yet
+    fn pending(&self) -> Vec<Url> {
+        self.relays
+            .iter()
+            .filter(|r| !r.accepted)
+            .map(|r| r.url.clone())
+            .collect()
+    }
+
+    /// Record the result of one attempt to send the event to the relays
+    fn record(&mut self, urls: &[Url], result: Result<&nostr_sdk::pool::Output<EventId>, String>) {
+        for ack in self.relays.iter_mut().filter(|r| urls.contains(&r.url)) {
+            match result {
+                Ok(output) => {
+                    if output.success.contains(&ack.url) {
+                        ack.accepted = true;
+                        ack.message = None;
+                    } else if let Some(message) = output.failed.get(&ack.url) {
+                        ack.message = message.clone();
+                    }
+                }
+                Err(ref e) => ack.message = Some(e.clone()),
+            }
         }
-    };
+        self.attempts += 1;
+        self.next_attempt = Timestamp::now() + Duration::from_secs(outbox_backoff(self.attempts));
+    }
 }
 
-/// Prints the version information
-pub fn version(ap: &Args) {
-    if ap.output.is_text() {
-        println!();
-        println!(
-            "  _|      _|      _|_|_|                     {}",
-            get_prog_without_ext()
-        );
-        print!("  _|_|    _|    _|             _~^~^~_       ");
-        println!("a Nostr CLI client written in Rust");
-        println!(
-            "  _|  _|  _|    _|         \\) /  o o  \\ (/   version {}",
-            get_version()
-        );
-        println!(
-            "  _|    _| |    _|           '_   -   _'     repo {}",
-            get_pkg_repository()
-        );
-        print!("  _|      _|      _|_|_|     / '-----' \\     ");
-        println!("please submit PRs to make this a better tool");
-        println!();
-    } else {
-        print_json(
-            &json!({
-                "program": get_prog_without_ext(),
-                "repo": get_pkg_repository(),
-                "version": get_version(),
-                "icon": "NC :crab:",
-            }),
-            ap.output,
-            0,
-            "",
-        );
-    }
+/// Seconds to wait before the next retry after the given number of attempts
+fn outbox_backoff(attempts: u32) -> u64 {
+    let factor = 1u64 << attempts.saturating_sub(1).min(20);
+    OUTBOX_BACKOFF_DEFAULT
+        .saturating_mul(factor)
+        .min(OUTBOX_BACKOFF_MAX)
 }
 
-/// Prints the installed version and the latest crates.io-available version
-pub fn version_check(ap: &Args) {
-    let key1 = "Installed version";
-    let value1 = get_version();
-    let key2: &str;
-    let value2: String;
-    let key3: &str;
-    let value3: String;
-    let name = env!("CARGO_PKG_NAME");
-    let location: String = "https://crates.io/crates/".to_owned() + name;
-    let version = env!("CARGO_PKG_VERSION");
-    let informer = update_informer::new(registry::Crates, name, version).check_version();
-    match informer {
-        Ok(Some(version)) => {
-            key2 = "New available version";
-            value2 = format!("{:?}", version);
-            key3 = "New version available at";
-            value3 = location;
+/// The outbox, events that still have to be relayed. It is kept in a
+/// file next to the credentials file.
+pub struct Outbox {
+    path: PathBuf,
+    /// the events sent in this run, with their status on each relay
+    sent: Mutex<Vec<OutboxEntry>>,
+    /// how to reach the inbox relays of the people an event is addressed to
+    routing: Option<InboxRouting>,
+    /// with json-spec the acknowledgements of the relays are printed
+    output: Output,
+}
+
+/// implementation of Outbox struct
+impl Outbox {
+    /// Default constructor
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            sent: Mutex::new(Vec::new()),
+            routing: None,
+            output: Output::default(),
         }
-        Ok(None) => {
-            key2 = "Status";
-            value2 = "You are up-to-date. You already have the latest version.".to_owned();
-            key3 = "Update required";
-            value3 = "No".to_owned();
+    }
+
+    /// Print the acknowledgements of the relays in the given output format
+    fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Also send events to the inbox relays of the people they are addressed to
+    fn with_routing(mut self, ap: &Args) -> Self {
+        self.routing = Some(InboxRouting::new(ap));
+        self
+    }
+
+    /// Read the events in the outbox, a missing file is an empty outbox
+    fn load(&self) -> Result<Vec<OutboxEntry>, Error> {
+        if !self.path.is_file() {
+            return Ok(Vec::new());
         }
-        Err(ref e) => {
-            key2 = "Error";
-            value2 = "Could not get latest version.".to_owned();
-            key3 = "Error message";
-            value3 = format!("{:?}", e);
+        let reader = File::open(&self.path)?;
+        match serde_json::from_reader(reader) {
+            Ok(entries) => Ok(entries),
+            Err(ref e) => {
+                error!(
+                    "Error: reading outbox {:?} failed. Reported error {:?}.",
+                    self.path, e
+                );
+                Err(Error::OutboxFailed)
+            }
         }
-    };
-    print_json(
-        &json!({
-            key1: value1,
-            key2: value2,
-            key3: value3,
-        }),
-        ap.output,
-        0,
-        "",
-    );
+    }
+
+    /// Write the events to the outbox, an empty outbox removes the file
+    fn save(&self, entries: &[OutboxEntry]) -> Result<(), Error> {
+        if entries.is_empty() {
+            if self.path.is_file() {
+                fs::remove_file(&self.path)?;
+            }
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        match serde_json::to_string_pretty(entries) {
+            Ok(json) => {
+                // the outbox reveals whom we write to, protect it like the credentials
+                let mut writer = File::create(&self.path)?;
+                writer.write_all(json.as_bytes())?;
+                Credentials::set_permissions(&writer)
+            }
+            Err(ref e) => {
+                error!(
+                    "Error: writing outbox {:?} failed. Reported error {:?}.",
+                    self.path, e
+                );
+                Err(Error::OutboxFailed)
+            }
+        }
+    }
+
+    /// Add an event to the outbox
+    fn queue(&self, entry: OutboxEntry) -> Result<(), Error> {
+        let mut entries = self.load()?;
+        entries.retain(|e| e.event.id != entry.event.id);
+        entries.push(entry);
+        self.save(&entries)
+    }
 }
 
-/// Asks the public for help
-pub fn contribute(ap: &Args) {
-    let text = format!(
-        "{}{}{}{}{}{}",
-        "This project is currently an experiment. ",
-        "If you know Rust and are interested in Nostr, please have a look at the repo ",
-        get_pkg_repository(),
-        ". Please contribute code to improve the ",
-        get_prog_without_ext(),
-        " Nostr CLI client. Safe!"
-    );
-    print_json(
-        &json!({
-            "Contribute": text,
-        }),
-        ap.output,
-        0,
-        "",
-    );
+/// Gets the path of the outbox, next to the credentials file
+fn get_outbox_path(ap: &Args) -> PathBuf {
+    get_credentials_actual_path(ap).with_file_name(OUTBOX_FILE_DEFAULT)
 }
 
-/// Reads metadata item from keyboard and puts it into the Args.
-fn get_name(ap: &mut Args) {
-    print!("Enter an optional name for this Nostr account (e.g. John Doe): ");
+/// How relays that are not configured can be connected in this run,
+/// e.g. the inbox relays of a recipient or the relay hint of an event.
+pub struct RelayAccess {
+    /// proxy and its credentials for the temporary connections
+    proxy: Option<SocketAddr>,
+    proxy_auth: Option<ProxyAuth>,
+    /// false if connecting without a proxy would bypass the proxy of our relays
+    allowed: bool,
+    timeout: Duration,
+    bridges: Socks5Bridges,
+}
+
+/// implementation of RelayAccess struct
+impl RelayAccess {
+    /// Default constructor, taking the proxy from the arguments
+    fn new(ap: &Args) -> Self {
+        Self {
+            proxy: ap.proxy_all,
+            proxy_auth: ap.proxy_all.and(get_proxy_auth(ap).unwrap_or(None)),
+            allowed: ap.proxy_all.is_some() || ap.creds.relays.iter().all(|r| r.proxy.is_none()),
+            timeout: Duration::from_secs(ap.fetch_timeout),
+            bridges: ap.bridges.clone(),
+        }
+    }
+
+    /// Temporarily use the relays with the given flag. Relays that are not
+    /// in the pool are added and connected, relays in the pool get the flag.
+    /// Release the returned relays when done.
+    async fn connect(&self, client: &Client, urls: &[Url], flag: RelayFlag) -> TemporaryRelays {
+        let mut temporary = TemporaryRelays::default();
+        if urls.is_empty() {
+            return temporary;
+        }
+        if !self.allowed {
+            warn!(
+                "Not connecting to relays {:?}, our relays use a proxy. Use --proxy-all to reach them.",
+                urls
+            );
+            return temporary;
+        }
+        let service_flag = match flag {
+            RelayFlag::Read => RelayServiceFlags::READ,
+            RelayFlag::Write => RelayServiceFlags::WRITE,
+            RelayFlag::Inbox => RelayServiceFlags::INBOX,
+            RelayFlag::Outbox => RelayServiceFlags::OUTBOX,
+        };
+        let mut connecting = tokio::task::JoinSet::new();
+        for url in urls {
+            if let Ok(r) = client.relay(url).await {
+                if !r.is_connected().await {
+                    debug!("Relay {:?} is not connected, skipping it.", url);
+                    continue;
+                }
+                // e.g. one of our read relays is the inbox relay of a recipient
+                if !r.flags_ref().has_all(service_flag) {
+                    r.flags_ref().add(service_flag);
+                    temporary.flagged.push((url.clone(), service_flag));
+                }
+                temporary.usable.push(url.clone());
+                continue;
+            }
+            let relay = Relay {
+                proxy: self.proxy,
+                proxy_auth: self.proxy_auth.clone(),
+                ..Relay::new(url.clone(), None).with_flags(&[flag])
+            };
+            if !add_relay(client, &self.bridges, &relay).await {
+                continue;
+            }
+            if let Ok(r) = client.relay(url).await {
+                let (url, timeout) = (url.clone(), self.timeout);
+                connecting.spawn(async move {
+                    r.connect(Some(timeout)).await;
+                    (url, r.is_connected().await)
+                });
+            }
+        }
+        // relays are connected in parallel, so that one slow relay does not delay the others
+        while let Some(joined) = connecting.join_next().await {
+            let Ok((url, connected)) = joined else {
+                continue;
+            };
+            if connected {
+                temporary.usable.push(url.clone());
+            } else {
+                debug!("Relay {:?} is not reachable.", url);
+            }
+            temporary.added.push(url);
+        }
+        temporary
+    }
+}
+
+/// Relays used for one action only, see RelayAccess::connect()
+#[derive(Default)]
+pub struct TemporaryRelays {
+    /// the connected relays that can be used
+    usable: Vec<Url>,
+    /// relays added to the pool
+    added: Vec<Url>,
+    /// relays of the pool that got a flag
+    flagged: Vec<(Url, RelayServiceFlags)>,
+}
+
+/// implementation of TemporaryRelays struct
+impl TemporaryRelays {
+    /// Remove the added relays and the flags again
+    async fn release(self, client: &Client) {
+        for url in &self.added {
+            if let Err(ref e) = client.remove_relay(url).await {
+                debug!("Removing relay {:?} failed. {:?}", url, e);
+            }
+        }
+        for (url, flag) in &self.flagged {
+            if let Ok(r) = client.relay(url).await {
+                r.flags_ref().remove(*flag);
+            }
+        }
+    }
+}
+
+/// The relays a person reads from, as published by them
+#[derive(Clone, Debug, Default)]
+struct RecipientRelays {
+    /// inbox relays of the NIP-65 relay list, or the relay hint of the contact
+    inbox: Vec<Url>,
+    /// relays for private messages (kind 10050, NIP-17)
+    dm: Vec<Url>,
+}
+
+/// Routing of events to the inbox relays of their recipients (NIP-65
+/// outbox model). DMs, replies and mentions reach people who read on
+/// relays that we do not write to.
+pub struct InboxRouting {
+    /// our own public key, we do not route to ourselves
+    own: Option<PublicKey>,
+    /// relay hints of the contacts, used when there is no relay list
+    hints: HashMap<PublicKey, Url>,
+    access: RelayAccess,
+    /// relays of the recipients looked up in this run
+    cache: Mutex<HashMap<PublicKey, RecipientRelays>>,
+}
+
+/// implementation of InboxRouting struct
+impl InboxRouting {
+    /// Default constructor, taking hints and proxy from the arguments
+    fn new(ap: &Args) -> Self {
+        let hints = ap
+            .creds
+            .contacts
+            .iter()
+            .filter_map(|c| {
+                let hint = Url::parse(&c.relay_url.as_ref()?.to_string()).ok()?;
+                is_relay_url(&hint).then_some((c.public_key, hint))
+            })
+            .collect();
+        Self {
+            own: PublicKey::from_bech32(&ap.creds.public_key_bech32).ok(),
+            hints,
+            access: RelayAccess::new(ap),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Look up the relay lists of all given people at once and cache them
+    async fn look_up(&self, client: &Client, pks: &[PublicKey]) {
+        let missing: Vec<PublicKey> = {
+            let cache = self.cache.lock().unwrap();
+            pks.iter()
+                .filter(|pk| !cache.contains_key(pk))
+                .copied()
+                .collect()
+        };
+        if missing.is_empty() {
+            return;
+        }
+        let filter = Filter::new()
+            .authors(missing.clone())
+            .kinds([Kind::RelayList, Kind::Custom(DM_RELAY_LIST_KIND)]);
+        let lists = match client
+            .get_events_of(vec![filter], EventSource::relays(Some(self.access.timeout)))
+            .await
+        {
+            Ok(events) => events,
+            Err(ref e) => {
+                debug!("Fetching relay lists of {:?} failed. {:?}", missing, e);
+                Vec::new()
+            }
+        };
+        let newest = |pk: &PublicKey, kind: Kind| {
+            lists
+                .iter()
+                .filter(|e| &e.pubkey == pk && e.kind == kind)
+                .max_by_key(|e| e.created_at)
+        };
+        let mut cache = self.cache.lock().unwrap();
+        for pk in missing {
+            let mut relays = RecipientRelays {
+                inbox: newest(&pk, Kind::RelayList)
+                    .map(inbox_relay_urls)
+                    .unwrap_or_default(),
+                dm: newest(&pk, Kind::Custom(DM_RELAY_LIST_KIND))
+                    .map(dm_relay_urls)
+                    .unwrap_or_default(),
+            };
+            if relays.inbox.is_empty() {
+                relays.inbox.extend(self.hints.get(&pk).cloned());
+            }
+            debug!("Relays of {:?} are {:?}.", pk, relays);
+            cache.insert(pk, relays);
+        }
+    }
+
+    /// The relays to send an event to a person: for private messages the
+    /// DM relays if they have any, else the inbox relays.
+    fn relays_for(&self, pk: &PublicKey, private: bool) -> Vec<Url> {
+        let cache = self.cache.lock().unwrap();
+        match cache.get(pk) {
+            Some(relays) if private && !relays.dm.is_empty() => relays.dm.clone(),
+            Some(relays) => relays.inbox.clone(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The inbox relays of a NIP-65 relay list, i.e. those marked read or unmarked
+fn inbox_relay_urls(list: &nostr_sdk::Event) -> Vec<Url> {
+    nip65::extract_relay_list(list)
+        .filter(|(url, metadata)| metadata != &&Some(RelayMetadata::Write) && is_relay_url(url))
+        .map(|(url, _)| url.clone())
+        .take(INBOX_RELAYS_MAX)
+        .collect()
+}
+
+/// The relays of a DM relay list (kind 10050, NIP-17)
+fn dm_relay_urls(list: &nostr_sdk::Event) -> Vec<Url> {
+    list.tags
+        .iter()
+        .filter_map(|t| match t.as_standardized() {
+            Some(TagStandard::Relay(url)) => Url::parse(&url.to_string()).ok(),
+            _ => None,
+        })
+        .filter(is_relay_url)
+        .take(INBOX_RELAYS_MAX)
+        .collect()
+}
+
+/// Temporarily connect to the inbox relays of the people the event is
+/// addressed to that are not among our write relays. The returned relays
+/// must be released again after sending.
+async fn connect_inbox_relays(
+    client: &Client,
+    routing: &InboxRouting,
+    event: &nostr_sdk::Event,
+    own: &[Url],
+) -> TemporaryRelays {
+    // lists like the contact list mention people but are not addressed to them
+    if event.kind.is_replaceable() || event.kind.is_parameterized_replaceable() {
+        return TemporaryRelays::default();
+    }
+    let pks: Vec<PublicKey> = event
+        .public_keys()
+        .filter(|pk| routing.own.as_ref() != Some(*pk))
+        .copied()
+        .collect();
+    if pks.is_empty() {
+        return TemporaryRelays::default();
+    }
+    if !routing.access.allowed {
+        warn!(
+            "Not sending to the inbox relays of {:?}, our relays use a proxy. Use --proxy-all to reach them.",
+            pks
+        );
+        return TemporaryRelays::default();
+    }
+    routing.look_up(client, &pks).await;
+    let mut urls: Vec<Url> = Vec::new();
+    for pk in &pks {
+        for url in routing.relays_for(pk, event.kind == Kind::GiftWrap) {
+            if !own.contains(&url) && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    routing
+        .access
+        .connect(client, &urls, RelayFlag::Write)
+        .await
+}
+
+/// A struct for the credentials. These will be serialized into JSON
+/// and written to the credentials.json file for permanent storage and
+/// future access.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Credentials {
+    secret_key_bech32: String, // nsec1...// private_key
+    public_key_bech32: String, // npub1...
+    relays: Vec<Relay>,
+    metadata: Metadata,
+    contacts: Vec<Contact>,
+    subscribed_pubkeys: Vec<PublicKey>,
+    subscribed_authors: Vec<PublicKey>,
+    // todo: zzz subscribed_channels should be EventId's ?
+    subscribed_channels: Vec<PublicKey>,
+    #[serde(default)]
+    subscribed_filters: Vec<NamedFilter>,
+    // contacts of the contact list as of the last sync or publish
+    #[serde(default)]
+    synced_contacts: Vec<PublicKey>,
+}
+
+impl AsRef<Credentials> for Credentials {
+    fn as_ref(&self) -> &Self {
+        self
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// implementation of Credentials struct
+impl Credentials {
+    /// Default constructor
+    fn new() -> Self {
+        Self {
+            secret_key_bech32: "".to_owned(),
+            public_key_bech32: "".to_owned(),
+            relays: Vec::new(),
+            metadata: Metadata::new(),
+            contacts: Vec::new(),
+            subscribed_pubkeys: Vec::new(),
+            subscribed_authors: Vec::new(),
+            subscribed_channels: Vec::new(),
+            subscribed_filters: Vec::new(),
+            synced_contacts: Vec::new(),
+        }
+    }
+
+    // /// Default constructor
+    // fn create(
+    //     secret_key_bech32: String,
+    //     public_key_bech32: String,
+    //     relays: Vec<Url>,
+    //     metadata: Metadata,
+    // ) -> Self {
+    //     Self {
+    //         secret_key_bech32,
+    //         public_key_bech32,
+    //         relays,
+    //         metadata,
+    //     }
+    // }
+
+    /// Constructor for Credentials
+    fn load(path: &Path) -> Result<Credentials, Error> {
+        let reader = File::open(path)?;
+        Credentials::set_permissions(&reader)?;
+        let credentials: Credentials = serde_json::from_reader(reader)?;
+        let mut credentialsfiltered = credentials.clone();
+        credentialsfiltered.secret_key_bech32 = "***".to_string();
+        info!("loaded credentials are: {:?}", credentialsfiltered);
+        Ok(credentials)
+    }
+
+    /// Writing the credentials to a file
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::create_dir_all(path.parent().ok_or(Error::NoHomeDirectory)?)?;
+        let writer = File::create(path)?;
+        serde_json::to_writer_pretty(&writer, self)?;
+        Credentials::set_permissions(&writer)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn set_permissions(file: &File) -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        let perms = file.metadata()?.permissions();
+        // is the file world-readable? if so, reset the permissions to 600
+        if perms.mode() & 0o4 == 0o4 {
+            file.set_permissions(fs::Permissions::from_mode(0o600))
+                .unwrap();
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn set_permissions(file: &File) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Gets the *default* path (including file name) of the credentials file
+/// The default path might not be the actual path as it can be overwritten with command line
+/// options.
+fn get_credentials_default_path() -> PathBuf {
+    let dir = ProjectDirs::from_path(PathBuf::from(get_prog_without_ext())).unwrap();
+    // fs::create_dir_all(dir.data_dir());
+    let dp = dir.data_dir().join(CREDENTIALS_FILE_DEFAULT);
+    debug!(
+        "Data will be put into project directory {:?}.",
+        dir.data_dir()
+    );
+    info!("Credentials file with private key is {}.", dp.display());
+    dp
+}
+
+/// Gets the *actual* path (including file name) of the credentials file
+/// The default path might not be the actual path as it can be overwritten with command line
+/// options.
+fn get_credentials_actual_path(ap: &Args) -> &PathBuf {
+    &ap.credentials
+}
+
+/// Return true if credentials file exists, false otherwise
+fn credentials_exist(ap: &Args) -> bool {
+    let dp = get_credentials_default_path();
+    let ap = get_credentials_actual_path(ap);
+    debug!(
+        "credentials_default_path = {:?}, credentials_actual_path = {:?}",
+        dp, ap
+    );
+    let exists = ap.is_file();
+    if exists {
+        debug!("{:?} exists and is file. Not sure if readable though.", ap);
+    } else {
+        debug!("{:?} does not exist or is not a file.", ap);
+    }
+    exists
+}
+
+/// Gets version number, static if available, otherwise default.
+fn get_version() -> &'static str {
+    VERSION_O.unwrap_or(VERSION)
+}
+
+/// Gets Rust package name, static if available, otherwise default.
+fn get_pkg_name() -> &'static str {
+    PKG_NAME_O.unwrap_or(PKG_NAME)
+}
+
+/// Gets Rust binary name, static if available, otherwise default.
+fn get_bin_name() -> &'static str {
+    BIN_NAME_O.unwrap_or(BIN_NAME)
+}
+
+/// Gets Rust package repository, static if available, otherwise default.
+fn get_pkg_repository() -> &'static str {
+    PKG_REPOSITORY_O.unwrap_or(PKG_REPOSITORY)
+}
+
+/// Gets program name without extension.
+fn get_prog_without_ext() -> &'static str {
+    get_bin_name() // with -rs suffix
+                   // get_pkg_name() // without -rs suffix
+}
+
+/// Prints the usage info
+pub fn usage() {
+    let help_str = Args::command().render_usage().to_string();
+    println!("{}", &help_str);
+    println!("Options:");
+    // let cmd = Args::command();
+    // // println!("{:?}", cmd);
+    // // for arg in cmd.get_arguments() {
+    // //         println!("{:?}",arg);
+    // // }
+    // // for arg in cmd.get_arguments() {
+    // //         println!("{}",arg); // bug in clap, panics
+    // // }
+    // for arg in cmd.get_arguments() {
+    //     let s = arg.get_help().unwrap().to_string();
+    //     let v: Vec<&str> = s.split("Details::").collect();
+    //     let val_names = arg.get_value_names().unwrap_or(&[]);
+    //     let mut pvalnames = false;
+    //     match arg.get_num_args() {
+    //         None => {}
+    //         Some(range) => {
+    //             println!("range {:?}", range);
+    //             if range != clap::builder::ValueRange::EMPTY {
+    //                 pvalnames = true;
+    //             }
+    //         }
+    //     }
+    //     if pvalnames {
+    //         println!(
+    //             "--{} [<{}>]:  {}",
+    //             arg.get_long().unwrap(),
+    //             val_names[0],
+    //             v[0]
+    //         );
+    //     } else {
+    //         println!("--{}: {}", arg.get_long().unwrap(), v[0]);
+    //     }
+    // }
+    let help_str = Args::command().render_help().to_string();
+    let v: Vec<&str> = help_str.split('\n').collect();
+    for l in v {
+        if l.starts_with("  -") || l.starts_with("      --") {
+            println!("{}", &l);
+        }
+    }
+}
+
+/// Prints the short help
+pub fn help() {
+    let help_str = Args::command().render_help().to_string();
+    // println!("{}", &help_str);
+    // regex to remove shortest pieces "Details:: ... \n  -"
+    // regex to remove shortest pieces "Details:: ... \n      --"
+    // regex to remove shortest pieces "Details:: ... \nPS:"
+    // 2 regex groups: delete and keep.
+    // [\S\s]*? ... match anything in a non-greedy fashion
+    // stop when either "PS:", "  -" or "      --" is reached
+    let re = Regex::new(r"(?P<del>[ ]+Details::[\S\s]*?)(?P<keep>\nPS:|\n  -|\n      --)").unwrap();
+    let after = re.replace_all(&help_str, "$keep");
+    print!("{}", &after.replace("\n\n", "\n")); // remove empty lines
+    println!("Use --manual to get more detailed help information.");
+}
+
+/// Prints the long help
+pub fn manual() {
+    let help_str = Args::command().render_long_help().to_string();
+    println!("{}", &help_str);
+}
+
+/// Prints the README.md file
+pub async fn readme() {
+    match reqwest::get(URL_README).await {
+        Ok(resp) => {
+            debug!("Got README.md file from URL {:?}.", URL_README);
+            println!("{}", resp.text().await.unwrap())
+        }
+        Err(ref e) => {
+            println!(
+                "Error getting README.md from {:#?}. Reported error {:?}.",
+                URL_README, e
+            );
+        }
+    };
+}
+
+/// Prints the version information
+pub fn version(ap: &Args) {
+    if ap.output.is_text() {
+        println!();
+        println!(
+            "  _|      _|      _|_|_|                     {}",
+            get_prog_without_ext()
+        );
+        print!("  _|_|    _|    _|             _~^~^~_       ");
+        println!("a Nostr CLI client written in Rust");
+        println!(
+            "  _|  _|  _|    _|         \\) /  o o  \\ (/   version {}",
+            get_version()
+        );
+        println!(
+            "  _|    _| |    _|           '_   -   _'     repo {}",
+            get_pkg_repository()
+        );
+        print!("  _|      _|      _|_|_|     / '-----' \\     ");
+        println!("please submit PRs to make this a better tool");
+        println!();
+    } else {
+        print_json(
+            &json!({
+                "program": get_prog_without_ext(),
+                "repo": get_pkg_repository(),
+                "version": get_version(),
+                "icon": "NC :crab:",
+            }),
+            ap.output,
+            0,
+            "",
+        );
+    }
+}
+
+/// Prints the installed version and the latest crates.io-available version
+pub fn version_check(ap: &Args) {
+    let key1 = "Installed version";
+    let value1 = get_version();
+    let key2: &str;
+    let value2: String;
+    let key3: &str;
+    let value3: String;
+    let name = env!("CARGO_PKG_NAME");
+    let location: String = "https://crates.io/crates/".to_owned() + name;
+    let version = env!("CARGO_PKG_VERSION");
+    let informer = update_informer::new(registry::Crates, name, version).check_version();
+    match informer {
+        Ok(Some(version)) => {
+            key2 = "New available version";
+            value2 = format!("{:?}", version);
+            key3 = "New version available at";
+            value3 = location;
+        }
+        Ok(None) => {
+            key2 = "Status";
+            value2 = "You are up-to-date. You already have the latest version.".to_owned();
+            key3 = "Update required";
+            value3 = "No".to_owned();
+        }
+        Err(ref e) => {
+            key2 = "Error";
+            value2 = "Could not get latest version.".to_owned();
+            key3 = "Error message";
+            value3 = format!("{:?}", e);
+        }
+    };
+    print_json(
+        &json!({
+            key1: value1,
+            key2: value2,
+            key3: value3,
+        }),
+        ap.output,
+        0,
+        "",
+    );
+}
+
+/// Asks the public for help
+pub fn contribute(ap: &Args) {
+    let text = format!(
+        "{}{}{}{}{}{}",
+        "This project is currently an experiment. ",
+        "If you know Rust and are interested in Nostr, please have a look at the repo ",
+        get_pkg_repository(),
+        ". Please contribute code to improve the ",
+        get_prog_without_ext(),
+        " Nostr CLI client. Safe!"
+    );
+    print_json(
+        &json!({
+            "Contribute": text,
+        }),
+        ap.output,
+        0,
+        "",
+    );
+}
+
+/// Reads metadata item from keyboard and puts it into the Args.
+fn get_name(ap: &mut Args) {
+    print!("Enter an optional name for this Nostr account (e.g. John Doe): ");
+    std::io::stdout()
+        .flush()
+        .expect("error: could not flush stdout");
+
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .expect("error: unable to read user input");
+
+    match input.trim() {
+        "" => {
+            info!("Name left empty. That is okay!");
+            ap.creds.metadata.name = None;
+        }
+        _ => {
+            ap.creds.metadata.name = Some(input.trim().to_owned());
+            info!("Name set to {:?}.", ap.creds.metadata.name);
+        }
+    }
+}
+
+/// Reads metadata item from keyboard and puts it into the Args.
+fn get_display_name(ap: &mut Args) {
+    print!("Enter an optional display name for this Nostr account (e.g. Jonnie): ");
+    std::io::stdout()
+        .flush()
+        .expect("error: could not flush stdout");
+
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .expect("error: unable to read user input");
+
+    match input.trim() {
+        "" => {
+            info!("Display name left empty. That is okay!");
+            ap.creds.metadata.display_name = None;
+        }
+        _ => {
+            ap.creds.metadata.display_name = Some(input.trim().to_owned());
+            info!("Display_name set to {:?}.", ap.creds.metadata.display_name);
+        }
+    }
+}
+
+/// Reads metadata item from keyboard and puts it into the Args.
+fn get_about(ap: &mut Args) {
+    print!(
+        "Enter an optional description for this Nostr account (e.g. nostr loving surfing dude): "
+    );
+    std::io::stdout()
+        .flush()
+        .expect("error: could not flush stdout");
+
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .expect("error: unable to read user input");
+
+    match input.trim() {
+        "" => {
+            info!("About left empty. That is okay!");
+            ap.creds.metadata.about = None;
+        }
+        _ => {
+            ap.creds.metadata.about = Some(input.trim().to_owned());
+            info!("About set to {:?}.", ap.creds.metadata.about);
+        }
+    }
+}
+
+/// Reads metadata item from keyboard and puts it into the Args.
+fn get_picture(ap: &mut Args) {
+    let mut repeat = true;
+    while repeat {
+        repeat = false;
+        print!("Enter an optional picture for this Nostr account (e.g. 'https://example.com/avatar.png' or 'file://./somedir/localfile.png'): ");
+        std::io::stdout()
+            .flush()
+            .expect("error: could not flush stdout");
+
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .expect("error: unable to read user input");
+
+        match input.trim() {
+            "" => {
+                info!("Picture left empty. That is okay!");
+                ap.creds.metadata.picture = None;
+            }
+            _ => match Url::parse(input.trim()) {
+                Ok(url) => {
+                    ap.creds.metadata.picture = Some(url.as_str().to_string());
+                    info!("Picture set to {:?}.", ap.creds.metadata.picture);
+                }
+                Err(ref e) => {
+                    error!(
+                        "{:?} is not a valid URL. Try again or leave empty. Reported error is {:?}.",
+                        input.trim(), e
+                    );
+                    repeat = true;
+                }
+            },
+        }
+    }
+}
+
+/// Reads metadata item from keyboard and puts it into the Args.
+fn get_nip05(ap: &mut Args) {
+    print!("Enter an optional nip05 name for this Nostr account (e.g. john@example.com): ");
     std::io::stdout()
         .flush()
         .expect("error: could not flush stdout");
 
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("error: unable to read user input");
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .expect("error: unable to read user input");
+
+    match input.trim() {
+        "" => {
+            info!("Nip05 left empty. That is okay!");
+            ap.creds.metadata.nip05 = None;
+        }
+        _ => {
+            ap.creds.metadata.nip05 = Some(input.trim().to_owned());
+            info!("Nip05 set to {:?}.", ap.creds.metadata.about);
+        }
+    }
+}
+
+/// Reads metadata item from keyboard.
+fn get_proxy() -> Option<SocketAddr> {
+    loop {
+        print!("Enter proxy for relay (e.g. https://127.0.0.1:9050) or leave empty for no proxy: ");
+        std::io::stdout()
+            .flush()
+            .expect("error: could not flush stdout");
+
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .expect("error: unable to read user input");
+
+        match input.trim() {
+            "" => {
+                info!("Proxy left empty. That is okay!");
+                return None;
+            }
+            _ => match SocketAddr::from_str(input.trim()) {
+                Ok(u) => {
+                    info!("proxy {:?} is accepted.", &u);
+                    return Some(u);
+                }
+                Err(ref e) => {
+                    error!(
+                        "{:?} is not a valid proxy. Try again or leave empty. Reported error is {:?}.",
+                        input.trim(), e
+                    );
+                }
+            },
+        }
+    }
+}
+
+/// Reads metadata item from keyboard and puts it into the Args.
+fn get_relays(ap: &mut Args) {
+    println!("Enter one or multiple optional relays for this Nostr account.");
+    let mut repeat = true;
+    while repeat {
+        print!("Enter relay name (e.g. wss://relay.example.com) or leave empty to move on: ");
+        std::io::stdout()
+            .flush()
+            .expect("error: could not flush stdout");
+
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .expect("error: unable to read user input");
+
+        match input.trim() {
+            "" => {
+                info!("Relay left empty. That is okay!");
+                repeat = false;
+            }
+            _ => match Url::parse(input.trim()) {
+                Ok(u) => {
+                    if u.scheme() == "wss" || u.scheme() == "ws" {
+                        let proxy = crate::get_proxy();
+                        ap.creds.relays.push(Relay::new(u.clone(), proxy));
+                        info!("relay {:?} {:?} added.", &u, proxy);
+                    } else {
+                        error!(
+                        "{:?} is not a valid URL. Scheme is not 'wss'. Try again or leave empty.",
+                        input.trim(),
+                    );
+                    }
+                }
+                Err(ref e) => {
+                    error!(
+                        "{:?} is not a valid URL. Try again or leave empty. Reported error is {:?}.",
+                        input.trim(), e
+                    );
+                }
+            },
+        }
+    }
+}
+
+/// Read credentials from disk
+pub(crate) fn read_credentials(ap: &mut Args) -> Result<(), Error> {
+    match Credentials::load(get_credentials_actual_path(ap)) {
+        Ok(c) => {
+            info!(
+                "Successfully loaded credentials from credentials file {:?}.",
+                get_credentials_actual_path(ap)
+            );
+            ap.creds = c;
+            Ok(())
+        }
+        Err(ref e) => {
+            if ap.nsec.is_some() {
+                debug!("no credentials file found or read")
+            } else {
+                error!(
+                    "Error: failed to read credentials file {:?}. Correct path? Error reported: {:?}.",
+                    get_credentials_actual_path(ap),
+                    e,
+                );
+            };
+            Err(Error::StorageFailure)
+        }
+    }
+}
+
+/// is this syntactically a valid relay string?
+pub(crate) fn is_relay_str(relay: &str) -> bool {
+    match Url::parse(relay) {
+        Ok(r) => r.scheme() == "wss",
+        Err(_) => false,
+    }
+}
+
+/// is this syntactically a valid relay string?
+pub(crate) fn is_relay_url(relay: &Url) -> bool {
+    if relay.scheme() != "wss" && relay.scheme() != "ws" {
+        return false;
+    }
+    relay.host_str().is_some()
+}
+
+/// Handle the --create_user CLI argument
+pub(crate) fn cli_create_user(ap: &mut Args) -> Result<(), Error> {
+    if !ap.create_user {
+        return Err(Error::MissingCliParameter);
+    }
+    if credentials_exist(ap) {
+        error!(concat!(
+            "Credentials file already exists. You have already created a user in ",
+            "the past. No --create-user is needed. Aborting. If you really want to create ",
+            "a new user, then delete the current user first with '--delete-user', or move ",
+            "or remove credentials file manually. ",
+            "Or just run your command again but without the '--create-user' option to ",
+            "use the currently existing user. ",
+        ));
+        return Err(Error::UserAlreadyExists);
+    }
+    match ap.name.as_ref() {
+        None => {
+            get_name(ap); // read from kb, put into metadata
+        }
+        Some(n) => {
+            if n.trim().is_empty() {
+                ap.creds.metadata.name = None;
+            } else {
+                ap.creds.metadata.name = Some(n.trim().to_owned());
+            }
+        }
+    }
+    match ap.display_name.as_ref() {
+        None => {
+            get_display_name(ap); // read from kb, put into metadata
+        }
+        Some(n) => {
+            if n.trim().is_empty() {
+                ap.creds.metadata.display_name = None;
+            } else {
+                ap.creds.metadata.display_name = Some(n.trim().to_owned());
+            }
+        }
+    }
+    match ap.about.as_ref() {
+        None => {
+            get_about(ap); // read from kb, put into metadata
+        }
+        Some(n) => {
+            if n.trim().is_empty() {
+                ap.creds.metadata.about = None;
+            } else {
+                ap.creds.metadata.about = Some(n.trim().to_owned());
+            }
+        }
+    }
+    match ap.picture.clone() {
+        None => {
+            get_picture(ap); // read from kb, put into metadata
+        }
+        Some(n) => {
+            if (n.scheme() == "none" || n.scheme() == "file")
+                && (n.path() == "/" || n.path() == "")
+                && n.host().is_none()
+            {
+                ap.creds.metadata.picture = None;
+            } else {
+                ap.creds.metadata.picture = Some(n.to_string());
+            }
+        }
+    }
+    match ap.nip05.as_ref() {
+        None => {
+            get_nip05(ap); // read from kb, put into metadata
+        }
+        Some(n) => {
+            if n.trim().is_empty() {
+                ap.creds.metadata.nip05 = None;
+            } else {
+                ap.creds.metadata.nip05 = Some(n.trim().to_owned());
+            }
+        }
+    }
+    info!("Metadata is: {:?}", ap.creds.metadata);
+
+    if ap.add_relay.is_empty() {
+        get_relays(ap);
+    } else {
+        let num = ap.add_relay.len();
+        let mut i = 0;
+        while i < num {
+            if is_relay_url(&ap.add_relay[i]) {
+                ap.creds.relays.push(
+                    Relay::new(ap.add_relay[i].clone(), ap.proxy)
+                        .with_flags(&ap.relay_flags)
+                        .with_proxy_auth(get_proxy_auth(ap)?),
+                );
+            } else {
+                error!(
+                    "Invalid relay syntax for relay {:?}. Skipping it.",
+                    ap.add_relay[i]
+                )
+            }
+            i += 1;
+        }
+    }
+    ap.creds.relays.dedup_by(|a, b| a.url == b.url);
+
+    let my_keys: Keys = if ap.nsec.is_some() {
+        // nsec key provided as argument, import it
+        // parses from bech32 as well as from hex
+        debug!("Importing private key from --nsec argument");
+        Keys::new(SecretKey::parse(ap.nsec.clone().unwrap())?)
+    } else {
+        // Generate new keys
+        debug!("A new private key is being generated for you");
+        Keys::generate()
+    };
+    debug!("Generated private key is: {:?}", my_keys.secret_key());
+    debug!("Generated public  key is: {:?}", my_keys.public_key());
+    match my_keys.public_key().to_bech32() {
+        Ok(k) => ap.creds.public_key_bech32 = k,
+        Err(ref e) => {
+            error!(
+                "Error: failed to convert public key. Aborting. Error reported: {:?}. ({:?})",
+                e, my_keys
+            );
+            return Err(Error::KeyFailure);
+        }
+    }
+    match my_keys.secret_key().to_bech32() {
+        Ok(k) => ap.creds.secret_key_bech32 = k,
+        Err(ref e) => {
+            error!(
+                "Error: failed to convert private key. Aborting. Error reported: {:?}. ({:?})",
+                e, my_keys
+            );
+            return Err(Error::KeyFailure);
+        }
+    }
+    match ap.creds.save(get_credentials_actual_path(ap)) {
+        Ok(()) => {
+            info!("Successfully stored credentials in credentials file {:?}. Protect it, it contains your private key. Data stored is {:?}.", get_credentials_actual_path(ap), &ap.creds);
+        }
+        Err(ref e) => {
+            error!(
+                "Error: failed to store credentials. Aborting. Error reported: {:?}. ({:?})",
+                e, my_keys
+            );
+            return Err(Error::StorageFailure);
+        }
+    }
+    Ok(())
+}
+
+/// Publish the metadata from the credentials as metadata event (kind 0)
+pub(crate) async fn publish_metadata(
+    client: &Client,
+    ap: &Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
+    let builder = EventBuilder::metadata(&ap.creds.metadata);
+    match send_builder_via_outbox(client, outbox, builder).await {
+        Ok(event_id) => {
+            info!("set_metadata successful. event_id {:?}.", event_id);
+            Ok(())
+        }
+        Err(ref e) => {
+            error!(
+                "Error: set_metadata failed. Metadata {:?} not published. Reported error {:?}.",
+                ap.creds.metadata, e
+            );
+            Err(Error::ChangeMetadataFailed)
+        }
+    }
+}
+
+/// Handle the --change-metadata CLI argument
+/// Change metadata in credentials and publish it.
+pub(crate) async fn cli_change_metadata(
+    client: &Client,
+    ap: &mut Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
+    if let Some(n) = ap.name.as_ref() {
+        if n.trim().is_empty() {
+            ap.creds.metadata.name = None;
+        } else {
+            ap.creds.metadata.name = Some(n.trim().to_owned());
+        }
+    }
+    if let Some(n) = ap.display_name.as_ref() {
+        if n.trim().is_empty() {
+            ap.creds.metadata.display_name = None;
+        } else {
+            ap.creds.metadata.display_name = Some(n.trim().to_owned());
+        }
+    }
+    if let Some(n) = ap.about.as_ref() {
+        if n.trim().is_empty() {
+            ap.creds.metadata.about = None;
+        } else {
+            ap.creds.metadata.about = Some(n.trim().to_owned());
+        }
+    }
+    if let Some(n) = ap.picture.as_ref() {
+        if (n.scheme() == "none" || n.scheme() == "file")
+            && (n.path() == "/" || n.path() == "")
+            && n.host().is_none()
+        {
+            ap.creds.metadata.picture = None;
+        } else {
+            ap.creds.metadata.picture = Some(n.to_string());
+        }
+    }
+    if let Some(n) = ap.nip05.as_ref() {
+        if n.trim().is_empty() {
+            ap.creds.metadata.nip05 = None;
+        } else {
+            ap.creds.metadata.nip05 = Some(n.trim().to_owned());
+        }
+    }
+    info!("Metadata is: {:?}", ap.creds.metadata);
+
+    if ap.nsec.is_none() {
+        debug!("Updating credentials file with new metadata.");
+        if let Err(ref e) = ap.creds.save(get_credentials_actual_path(ap)) {
+            error!(
+                "Error: writing new metadata {:?} to credentials file failed. Reported error {:?}.",
+                ap.creds.metadata, e
+            );
+            return Err(Error::StorageFailure);
+        }
+    } else {
+        debug!("Not updating credentials file with new metadata.")
+    }
+    publish_metadata(client, ap, outbox).await
+}
 
-    match input.trim() {
-        "" => {
-            info!("Name left empty. That is okay!");
-            ap.creds.metadata.name = None;
+/// Compare local and remote metadata field-by-field.
+/// Returns one entry per differing field, or an empty vector if both are equal.
+pub(crate) fn metadata_differences(local: &Metadata, remote: &Metadata) -> Vec<Value> {
+    let localv = json!(local);
+    let remotev = json!(remote);
+    let empty = serde_json::Map::new();
+    let localo = localv.as_object().unwrap_or(&empty);
+    let remoteo = remotev.as_object().unwrap_or(&empty);
+    let mut fields: Vec<&String> = localo.keys().chain(remoteo.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let l = localo.get(field).unwrap_or(&Value::Null);
+            let r = remoteo.get(field).unwrap_or(&Value::Null);
+            if l == r {
+                None
+            } else {
+                Some(json!({"field": field, "local": l, "remote": r}))
+            }
+        })
+        .collect()
+}
+
+/// Handle the --show-metadata CLI argument
+/// Print local metadata and optionally compare it to the metadata on the relays.
+pub(crate) async fn cli_show_metadata(client: &Client, ap: &Args) -> Result<(), Error> {
+    // the metadata object is the content of a metadata event, so it adheres to the spec
+    let output = match ap.output {
+        Output::JsonSpec => Output::Json,
+        o => o,
+    };
+    print_json(&json!(ap.creds.metadata), output, 0, "");
+    if ap.show_metadata != Some(Some(ShowMetadata::Remote)) {
+        return Ok(());
+    }
+    let pkey = str_to_pubkey(&ap.creds.public_key_bech32)?;
+    let filter = Filter::new().author(pkey).kind(Kind::Metadata).limit(1);
+    let timeout = Some(Duration::from_secs(ap.fetch_timeout));
+    let events = match client
+        .get_events_of(vec![filter], EventSource::relays(timeout))
+        .await
+    {
+        Ok(events) => events,
+        Err(ref e) => {
+            error!(
+                "Error: fetching metadata from relays failed. Reported error {:?}.",
+                e
+            );
+            return Err(Error::ShowMetadataFailed);
         }
-        _ => {
-            ap.creds.metadata.name = Some(input.trim().to_owned());
-            info!("Name set to {:?}.", ap.creds.metadata.name);
+    };
+    let event = match events.iter().max_by_key(|e| e.created_at) {
+        Some(event) => event,
+        None => {
+            error!("Error: no metadata event found on the relays.");
+            print_json(
+                &json!({
+                    "remote_metadata": null,
+                    "in_sync": false,
+                }),
+                ap.output,
+                0,
+                "",
+            );
+            return Err(Error::ShowMetadataFailed);
+        }
+    };
+    match Metadata::from_json(&event.content) {
+        Ok(remote) => {
+            let differences = metadata_differences(&ap.creds.metadata, &remote);
+            print_json(
+                &json!({
+                    "remote_metadata": remote,
+                    "remote_event_id": event.id,
+                    "remote_created_at": event.created_at,
+                    "in_sync": differences.is_empty(),
+                    "differences": differences,
+                }),
+                ap.output,
+                0,
+                "",
+            );
+            Ok(())
+        }
+        Err(ref e) => {
+            error!(
+                "Error: metadata event {:?} from relays has invalid content {:?}. Reported error {:?}.",
+                event.id, event.content, e
+            );
+            Err(Error::ShowMetadataFailed)
         }
     }
 }
 
-/// Reads metadata item from keyboard and puts it into the Args.
-fn get_display_name(ap: &mut Args) {
-    print!("Enter an optional display name for this Nostr account (e.g. Jonnie): ");
+/// Reads confirmation for deleting the user from keyboard.
+/// Returns true only if 'yes' was typed.
+fn get_delete_user_confirmation(ap: &Args) -> bool {
+    get_confirmation(&format!(
+        "Delete user {:?} and its private key in {:?} irrecoverably?",
+        ap.creds.public_key_bech32,
+        get_credentials_actual_path(ap)
+    ))
+}
+
+/// Ask a yes/no question on the terminal, only 'yes' confirms.
+fn get_confirmation(question: &str) -> bool {
+    if !atty::is(Stream::Stdin) {
+        error!(
+            "Cannot ask for confirmation because stdin is not a terminal. Use --yes to confirm."
+        );
+        return false;
+    }
+    print!("{} Type 'yes' to confirm: ", question);
     std::io::stdout()
         .flush()
         .expect("error: could not flush stdout");
@@ -1379,518 +3358,1550 @@ fn get_display_name(ap: &mut Args) {
     io::stdin()
         .read_line(&mut input)
         .expect("error: unable to read user input");
+    input.trim().eq_ignore_ascii_case("yes")
+}
 
-    match input.trim() {
-        "" => {
-            info!("Display name left empty. That is okay!");
-            ap.creds.metadata.display_name = None;
+/// Overwrite a file with zeros, flush it to disk and then remove it.
+fn wipe_file(path: &Path) -> Result<(), Error> {
+    let len = fs::metadata(path)?.len();
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0u8; len as usize])?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Publish empty metadata and ask relays to delete all own events (NIP-09).
+pub(crate) async fn delete_user_remotely(
+    client: &Client,
+    ap: &Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
+    let pkey = str_to_pubkey(&ap.creds.public_key_bech32)?;
+    let filter = Filter::new().author(pkey);
+    let timeout = Some(Duration::from_secs(ap.fetch_timeout));
+    let events = match client
+        .get_events_of(vec![filter], EventSource::relays(timeout))
+        .await
+    {
+        Ok(events) => events,
+        Err(ref e) => {
+            error!(
+                "Error: fetching own events from relays failed. Reported error {:?}.",
+                e
+            );
+            return Err(Error::DeleteUserFailed);
         }
-        _ => {
-            ap.creds.metadata.display_name = Some(input.trim().to_owned());
-            info!("Display_name set to {:?}.", ap.creds.metadata.display_name);
+    };
+    // metadata is replaced by an empty one below, deletions cannot be deleted
+    let ids: Vec<EventId> = events
+        .iter()
+        .filter(|e| e.kind != Kind::EventDeletion && e.kind != Kind::Metadata)
+        .map(|e| e.id)
+        .collect();
+    debug!("Requesting deletion of {} own events.", ids.len());
+    let mut err_count = 0usize;
+    // keep deletion events reasonably small, relays limit event sizes
+    for chunk in ids.chunks(100) {
+        match send_builder_via_outbox(client, outbox, EventBuilder::delete(chunk.to_vec())).await {
+            Ok(event_id) => debug!(
+                "Deletion event sent successfully. event_id {:?}, {} events referenced.",
+                event_id,
+                chunk.len()
+            ),
+            Err(ref e) => {
+                err_count += 1;
+                error!("Error: deletion event failed. Reported error {:?}.", e);
+            }
+        }
+    }
+    match send_builder_via_outbox(client, outbox, EventBuilder::metadata(&Metadata::new())).await {
+        Ok(event_id) => debug!("Empty metadata sent successfully. {:?}", event_id),
+        Err(ref e) => {
+            err_count += 1;
+            error!(
+                "Error: publishing empty metadata failed. Reported error {:?}.",
+                e
+            );
         }
     }
+    if err_count != 0 {
+        Err(Error::DeleteUserFailed)
+    } else {
+        Ok(())
+    }
 }
 
-/// Reads metadata item from keyboard and puts it into the Args.
-fn get_about(ap: &mut Args) {
-    print!(
-        "Enter an optional description for this Nostr account (e.g. nostr loving surfing dude): "
-    );
-    std::io::stdout()
-        .flush()
-        .expect("error: could not flush stdout");
-
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("error: unable to read user input");
-
-    match input.trim() {
-        "" => {
-            info!("About left empty. That is okay!");
-            ap.creds.metadata.about = None;
+/// Handle the --delete-user CLI argument
+/// Wipe the credentials file, optionally after deleting data on the relays.
+/// Pass a client and an outbox to also delete data on the relays.
+pub(crate) async fn cli_delete_user(
+    remote: Option<(&Client, &Outbox)>,
+    ap: &Args,
+) -> Result<(), Error> {
+    if !credentials_exist(ap) {
+        error!(
+            "Credentials file {:?} does not exist. There is no user to delete.",
+            get_credentials_actual_path(ap)
+        );
+        return Err(Error::NoCredentialsFound);
+    }
+    if !ap.yes && !get_delete_user_confirmation(ap) {
+        return Error::custom("Deleting user was not confirmed. Nothing was deleted.");
+    }
+    if let Some((client, outbox)) = remote {
+        if let Err(ref e) = delete_user_remotely(client, ap, outbox).await {
+            error!(
+                "Error: deleting user data on relays failed. Keeping credentials so that you can try again. Reported error {:?}.",
+                e
+            );
+            return Err(Error::DeleteUserFailed);
         }
-        _ => {
-            ap.creds.metadata.about = Some(input.trim().to_owned());
-            info!("About set to {:?}.", ap.creds.metadata.about);
+    }
+    for store_path in [get_event_store_path(ap), get_event_sync_path(ap)] {
+        if store_path.is_file() {
+            match wipe_file(&store_path) {
+                Ok(()) => info!("Event store {:?} was overwritten and removed.", store_path),
+                Err(ref e) => error!(
+                    "Error: wiping event store {:?} failed. Reported error {:?}.",
+                    store_path, e
+                ),
+            }
+        }
+    }
+    // the outbox holds events signed by the deleted key
+    let outbox_path = get_outbox_path(ap);
+    if outbox_path.is_file() {
+        match wipe_file(&outbox_path) {
+            Ok(()) => info!("Outbox {:?} was overwritten and removed.", outbox_path),
+            Err(ref e) => error!(
+                "Error: wiping outbox {:?} failed. Reported error {:?}.",
+                outbox_path, e
+            ),
+        }
+    }
+    match wipe_file(get_credentials_actual_path(ap)) {
+        Ok(()) => {
+            info!(
+                "Credentials file {:?} was overwritten and removed.",
+                get_credentials_actual_path(ap)
+            );
+            Ok(())
+        }
+        Err(ref e) => {
+            error!(
+                "Error: wiping credentials file {:?} failed. Reported error {:?}.",
+                get_credentials_actual_path(ap),
+                e
+            );
+            Err(Error::DeleteUserFailed)
         }
     }
 }
 
-/// Reads metadata item from keyboard and puts it into the Args.
-fn get_picture(ap: &mut Args) {
-    let mut repeat = true;
-    while repeat {
-        repeat = false;
-        print!("Enter an optional picture for this Nostr account (e.g. 'https://example.com/avatar.png' or 'file://./somedir/localfile.png'): ");
-        std::io::stdout()
-            .flush()
-            .expect("error: could not flush stdout");
+/// Gets the proxy username and password given with --proxy-user
+fn get_proxy_auth(ap: &Args) -> Result<Option<ProxyAuth>, Error> {
+    ap.proxy_user
+        .as_deref()
+        .map(ProxyAuth::from_str)
+        .transpose()
+}
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("error: unable to read user input");
+/// The relay as it is connected in this run, with --proxy-all
+/// all relays are connected through the same proxy.
+fn route_relay(ap: &Args, relay: &Relay) -> Result<Relay, Error> {
+    match ap.proxy_all {
+        Some(addr) => Ok(Relay {
+            proxy: Some(addr),
+            proxy_auth: get_proxy_auth(ap)?,
+            ..relay.clone()
+        }),
+        None => Ok(relay.clone()),
+    }
+}
 
-        match input.trim() {
-            "" => {
-                info!("Picture left empty. That is okay!");
-                ap.creds.metadata.picture = None;
+/// Add relays to from Credentials to client
+pub(crate) async fn add_relays_from_creds(client: &mut Client, ap: &mut Args) -> Result<(), Error> {
+    let mut err_count = 0u32;
+    for relay in &ap.creds.relays {
+        let relay = match route_relay(ap, relay) {
+            Ok(relay) => relay,
+            Err(ref e) => {
+                error!(
+                    "Error: relay {:?} not added. Reported error {:?}.",
+                    relay.url, e
+                );
+                err_count += 1;
+                continue;
             }
-            _ => match Url::parse(input.trim()) {
-                Ok(url) => {
-                    ap.creds.metadata.picture = Some(url.as_str().to_string());
-                    info!("Picture set to {:?}.", ap.creds.metadata.picture);
-                }
-                Err(ref e) => {
-                    error!(
-                        "{:?} is not a valid URL. Try again or leave empty. Reported error is {:?}.",
-                        input.trim(), e
-                    );
-                    repeat = true;
-                }
-            },
+        };
+        if !add_relay(client, &ap.bridges, &relay).await {
+            err_count += 1
         }
     }
+    match err_count {
+        0 => Ok(()),
+        _ => Err(Error::AddRelayFailed),
+    }
 }
 
-/// Reads metadata item from keyboard and puts it into the Args.
-fn get_nip05(ap: &mut Args) {
-    print!("Enter an optional nip05 name for this Nostr account (e.g. john@example.com): ");
-    std::io::stdout()
-        .flush()
-        .expect("error: could not flush stdout");
-
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("error: unable to read user input");
+/// The address to connect to the relay through, if any. With a
+/// username and password this is a local bridge to the proxy.
+fn relay_proxy_addr(bridges: &Socks5Bridges, relay: &Relay) -> Result<Option<SocketAddr>, Error> {
+    match (relay.proxy, &relay.proxy_auth) {
+        (None, _) => Ok(None),
+        (Some(addr), None) => Ok(Some(addr)),
+        // the SDK cannot authenticate, a local bridge does it on its behalf
+        (Some(addr), Some(auth)) => {
+            let host = relay.url.host_str().unwrap_or_default();
+            Ok(Some(bridges.get(addr, auth, host)?))
+        }
+    }
+}
 
-    match input.trim() {
-        "" => {
-            info!("Nip05 left empty. That is okay!");
-            ap.creds.metadata.nip05 = None;
+async fn add_relay(client: &Client, bridges: &Socks5Bridges, relay: &Relay) -> bool {
+    let url = &relay.url;
+    let proxy = relay.proxy;
+    let mut opts = relay.options();
+    match relay_proxy_addr(bridges, relay) {
+        Ok(None) => (),
+        Ok(Some(addr)) => {
+            opts = opts
+                .connection_mode(ConnectionMode::Proxy(addr))
+                .retry_sec(11)
         }
-        _ => {
-            ap.creds.metadata.nip05 = Some(input.trim().to_owned());
-            info!("Nip05 set to {:?}.", ap.creds.metadata.about);
+        Err(ref e) => {
+            error!(
+                "Error: proxy {:?} for relay {:?} not available. Relay not added. Reported error {:?}.",
+                proxy, url, e
+            );
+            return false;
+        }
+    }
+    match client.pool().add_relay(url, opts).await {
+        Ok(value) => {
+            let status = if value { "successful" } else { "already added" };
+            debug!(
+                "add_relay...() with relay {:?} with proxy {:?}: {}.",
+                url, proxy, status
+            );
+            true
+        }
+        Err(ref e) => {
+            error!(
+                "Error: add_relay...() returned error. Relay {:?} not added. \
+                    Reported error {:?}.",
+                url, e
+            );
+            false
         }
     }
 }
 
-/// Reads metadata item from keyboard.
-fn get_proxy() -> Option<SocketAddr> {
-    loop {
-        print!("Enter proxy for relay (e.g. https://127.0.0.1:9050) or leave empty for no proxy: ");
-        std::io::stdout()
-            .flush()
-            .expect("error: could not flush stdout");
-
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("error: unable to read user input");
+/// A local SOCKS5 proxy that forwards the connections of the relays
+/// through a proxy, authenticating with username and password.
+#[derive(Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Socks5Bridge {
+    upstream: SocketAddr,
+    auth: ProxyAuth,
+    local: SocketAddr,
+    /// the bridge only connects to the hosts of the relays using it
+    hosts: Arc<Mutex<HashSet<String>>>,
+    task: tokio::task::AbortHandle,
+}
 
-        match input.trim() {
-            "" => {
-                info!("Proxy left empty. That is okay!");
-                return None;
-            }
-            _ => match SocketAddr::from_str(input.trim()) {
-                Ok(u) => {
-                    info!("proxy {:?} is accepted.", &u);
-                    return Some(u);
-                }
-                Err(ref e) => {
-                    error!(
-                        "{:?} is not a valid proxy. Try again or leave empty. Reported error is {:?}.",
-                        input.trim(), e
-                    );
-                }
-            },
-        }
+/// The bridge stops when it is dropped, together with its connections
+impl Drop for Socks5Bridge {
+    fn drop(&mut self) {
+        self.task.abort();
+        debug!(
+            "SOCKS5 bridge {:?} to proxy {:?} stopped.",
+            self.local, self.upstream
+        );
     }
 }
 
-/// Reads metadata item from keyboard and puts it into the Args.
-fn get_relays(ap: &mut Args) {
-    println!("Enter one or multiple optional relays for this Nostr account.");
-    let mut repeat = true;
-    while repeat {
-        print!("Enter relay name (e.g. wss://relay.example.com) or leave empty to move on: ");
-        std::io::stdout()
-            .flush()
-            .expect("error: could not flush stdout");
+/// The SOCKS5 bridges of this run, one per proxy and credentials,
+/// shared by all relays and clients. They stop when the last copy is dropped.
+#[derive(Clone, Debug, Default)]
+pub struct Socks5Bridges {
+    bridges: Arc<Mutex<Vec<Socks5Bridge>>>,
+}
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("error: unable to read user input");
+/// implementation of Socks5Bridges struct
+impl Socks5Bridges {
+    /// Get the bridge to the given proxy, starting it if needed, and allow
+    /// it to connect to the host. The SDK can only use a SOCKS5 proxy
+    /// without authentication, so the bridge only serves connections from
+    /// this process. Returns the address of the bridge on localhost.
+    #[cfg(target_os = "linux")]
+    fn get(&self, upstream: SocketAddr, auth: &ProxyAuth, host: &str) -> Result<SocketAddr, Error> {
+        let mut bridges = self.bridges.lock().unwrap();
+        if let Some(bridge) = bridges
+            .iter()
+            .find(|b| b.upstream == upstream && &b.auth == auth)
+        {
+            bridge.hosts.lock().unwrap().insert(host.to_owned());
+            return Ok(bridge.local);
+        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let local = listener.local_addr()?;
+        let hosts = Arc::new(Mutex::new(HashSet::from([host.to_owned()])));
+        let task = tokio::spawn(socks5_bridge_accept(
+            listener,
+            upstream,
+            auth.clone(),
+            hosts.clone(),
+        ));
+        debug!("SOCKS5 bridge {:?} to proxy {:?} started.", local, upstream);
+        bridges.push(Socks5Bridge {
+            upstream,
+            auth: auth.clone(),
+            local,
+            hosts,
+            task: task.abort_handle(),
+        });
+        Ok(local)
+    }
+
+    /// Without a way to tell which process connects to the bridge, any local
+    /// user could use the proxy with our credentials. So there is no bridge.
+    #[cfg(not(target_os = "linux"))]
+    fn get(
+        &self,
+        upstream: SocketAddr,
+        _auth: &ProxyAuth,
+        _host: &str,
+    ) -> Result<SocketAddr, Error> {
+        error!(
+            "Error: proxy {:?} with username and password is not supported on this platform.",
+            upstream
+        );
+        Err(Error::UnsupportedCliParameter(
+            "--proxy-user is only supported on Linux",
+        ))
+    }
+}
 
-        match input.trim() {
-            "" => {
-                info!("Relay left empty. That is okay!");
-                repeat = false;
-            }
-            _ => match Url::parse(input.trim()) {
-                Ok(u) => {
-                    if u.scheme() == "wss" || u.scheme() == "ws" {
-                        let proxy = crate::get_proxy();
-                        ap.creds.relays.push(Relay::new(u.clone(), proxy));
-                        info!("relay {:?} {:?} added.", &u, proxy);
-                    } else {
+/// Accept the connections to a SOCKS5 bridge. Dropping the connections
+/// aborts them when the bridge stops.
+#[cfg(target_os = "linux")]
+async fn socks5_bridge_accept(
+    listener: tokio::net::TcpListener,
+    upstream: SocketAddr,
+    auth: ProxyAuth,
+    hosts: Arc<Mutex<HashSet<String>>>,
+) {
+    let local = listener.local_addr().ok();
+    let mut connections = tokio::task::JoinSet::new();
+    loop {
+        tokio::select! {
+            conn = listener.accept() => {
+                let (stream, peer) = match conn {
+                    Ok(conn) => conn,
+                    Err(ref e) => {
                         error!(
-                        "{:?} is not a valid URL. Scheme is not 'wss'. Try again or leave empty.",
-                        input.trim(),
-                    );
+                            "Error: SOCKS5 bridge {:?} failed. Reported error {:?}.",
+                            local, e
+                        );
+                        return;
                     }
-                }
-                Err(ref e) => {
-                    error!(
-                        "{:?} is not a valid URL. Try again or leave empty. Reported error is {:?}.",
-                        input.trim(), e
-                    );
-                }
-            },
+                };
+                let auth = auth.clone();
+                let hosts = hosts.clone();
+                connections.spawn(async move {
+                    // reading /proc blocks
+                    let own = match (stream.local_addr(), peer) {
+                        (Ok(local), peer) => tokio::task::spawn_blocking(move || is_own_connection(peer, local))
+                            .await
+                            .unwrap_or(false),
+                        _ => false,
+                    };
+                    if !own {
+                        warn!("SOCKS5 bridge refused connection from other process {:?}.", peer);
+                        return;
+                    }
+                    if let Err(ref e) = socks5_bridge_connection(stream, upstream, &auth, &hosts).await {
+                        debug!("SOCKS5 bridge connection failed. Reported error {:?}.", e);
+                    }
+                });
+            }
+            // forget finished connections
+            Some(_) = connections.join_next(), if !connections.is_empty() => (),
         }
     }
 }
 
-/// Read credentials from disk
-pub(crate) fn read_credentials(ap: &mut Args) -> Result<(), Error> {
-    match Credentials::load(get_credentials_actual_path(&ap)) {
-        Ok(c) => {
-            info!(
-                "Successfully loaded credentials from credentials file {:?}.",
-                get_credentials_actual_path(&ap)
-            );
-            ap.creds = c;
-            return Ok(());
+/// Whether the peer of a connection to the bridge is a socket of this
+/// process, found via the socket inode in /proc.
+#[cfg(target_os = "linux")]
+fn is_own_connection(peer: SocketAddr, local: SocketAddr) -> bool {
+    // addresses in /proc/net/tcp* are hex, each 32 bit word in network byte order
+    let hex = |addr: SocketAddr| {
+        let words: Vec<u32> = match addr {
+            SocketAddr::V4(a) => vec![u32::from_ne_bytes(a.ip().octets())],
+            SocketAddr::V6(a) => a
+                .ip()
+                .octets()
+                .chunks(4)
+                .map(|w| u32::from_ne_bytes([w[0], w[1], w[2], w[3]]))
+                .collect(),
+        };
+        let ip: String = words.iter().map(|w| format!("{w:08X}")).collect();
+        format!("{ip}:{:04X}", addr.port())
+    };
+    let table = match peer {
+        SocketAddr::V4(_) => "/proc/self/net/tcp",
+        SocketAddr::V6(_) => "/proc/self/net/tcp6",
+    };
+    let (peer, local) = (hex(peer), hex(local));
+    let inode = fs::read_to_string(table)
+        .unwrap_or_default()
+        .lines()
+        .map(|l| l.split_whitespace().collect::<Vec<&str>>())
+        .find(|f| f.len() > 9 && f[1] == peer && f[2] == local)
+        .map(|f| format!("socket:[{}]", f[9]));
+    let Some(inode) = inode else {
+        return false;
+    };
+    fs::read_dir("/proc/self/fd")
+        .map(|fds| {
+            fds.flatten()
+                .any(|fd| fs::read_link(fd.path()).is_ok_and(|l| l.as_os_str() == inode.as_str()))
+        })
+        .unwrap_or(false)
+}
+
+/// Serve one CONNECT request of the local SOCKS5 bridge.
+#[cfg(target_os = "linux")]
+async fn socks5_bridge_connection(
+    mut stream: tokio::net::TcpStream,
+    upstream: SocketAddr,
+    auth: &ProxyAuth,
+    hosts: &Mutex<HashSet<String>>,
+) -> Result<(), Error> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    // greeting: version, number of methods, methods; we accept "no authentication"
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).await?;
+    let mut methods = vec![0u8; head[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    if head[0] != 5 || !methods.contains(&0) {
+        stream.write_all(&[5, 0xff]).await?;
+        return Err(Error::ProxyFailed);
+    }
+    stream.write_all(&[5, 0]).await?;
+    // request: version, command, reserved, address type, address, port
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    if request[1] != 1 {
+        // only CONNECT is needed for websockets
+        stream.write_all(&[5, 7, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(Error::ProxyFailed);
+    }
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            let mut name = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8_lossy(&name).into_owned()
+        }
+        4 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            std::net::Ipv6Addr::from(ip).to_string()
+        }
+        _ => {
+            stream.write_all(&[5, 8, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+            return Err(Error::ProxyFailed);
         }
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port).await?;
+    let port = u16::from_be_bytes(port);
+    if !hosts.lock().unwrap().contains(&host) {
+        error!(
+            "Error: SOCKS5 bridge refused connection to {}:{}, it is not a relay host.",
+            host, port
+        );
+        // connection not allowed by ruleset
+        stream.write_all(&[5, 2, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(Error::ProxyFailed);
+    }
+    let upstream_stream = tokio_socks::tcp::Socks5Stream::connect_with_password(
+        upstream,
+        (host.as_str(), port),
+        &auth.username,
+        &auth.password,
+    )
+    .await;
+    let mut upstream_stream = match upstream_stream {
+        Ok(upstream_stream) => upstream_stream,
         Err(ref e) => {
-            if ap.nsec.is_some() {
-                debug!("no credentials file found or read")
-            } else {
+            error!(
+                "Error: proxy {:?} refused connection to {}:{}. Reported error {:?}.",
+                upstream, host, port, e
+            );
+            stream.write_all(&[5, 1, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+            return Err(Error::ProxyFailed);
+        }
+    };
+    stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream_stream).await?;
+    Ok(())
+}
+
+/// Handle the --add_relay CLI argument.
+/// Add relays from --add-relay.
+pub(crate) async fn cli_add_relay(client: &mut Client, ap: &mut Args) -> Result<(), Error> {
+    let mut err_count = 0u32;
+    let urls: Vec<&Url> = ap
+        .add_relay
+        .iter()
+        .filter(|url| match is_relay_url(url) {
+            true => true,
+            false => {
+                err_count += 1;
                 error!(
-                    "Error: failed to read credentials file {:?}. Correct path? Error reported: {:?}.",
-                    get_credentials_actual_path(&ap),
-                    e,
+                    "Error: Relay {:?} is syntactically not correct. Relay not added.",
+                    url,
                 );
-            };
-            return Err(Error::StorageFailure);
+                false
+            }
+        })
+        .collect();
+
+    for url in urls {
+        let relay = get_proxy_auth(ap).and_then(|proxy_auth| {
+            let relay = Relay::new(url.clone(), ap.proxy)
+                .with_flags(&ap.relay_flags)
+                .with_proxy_auth(proxy_auth);
+            Ok((route_relay(ap, &relay)?, relay))
+        });
+        let (routed, relay) = match relay {
+            Ok(relays) => relays,
+            Err(ref e) => {
+                error!("Error: relay {:?} not added. Reported error {:?}.", url, e);
+                err_count += 1;
+                continue;
+            }
+        };
+        // a relay added again gets the new flags and proxy
+        if ap.creds.relays.iter().any(|r| r.url == *url) {
+            if let Err(ref e) = client.remove_relay(url).await {
+                debug!("Relay {:?} was not in client. {:?}", url, e);
+            }
+            ap.creds.relays.retain(|r| r.url != *url);
+        }
+        match add_relay(client, &ap.bridges, &routed).await {
+            true => ap.creds.relays.push(relay),
+            false => err_count += 1,
         }
     }
-}
 
-/// is this syntactically a valid relay string?
-pub(crate) fn is_relay_str(relay: &str) -> bool {
-    match Url::parse(relay) {
-        Ok(r) => {
-            if r.scheme() == "wss" {
-                true
-            } else {
-                false
+    if ap.nsec.is_none() || ap.create_user {
+        debug!("Creating or updating credentials file to add new relays.");
+        match ap.creds.save(get_credentials_actual_path(ap)) {
+            Ok(()) => {
+                debug!(
+                    "writing new relays {:?} to credentials file successful.",
+                    ap.creds.relays
+                );
+            }
+            Err(ref e) => {
+                error!(
+                    "Error: writing new relays {:?} to credentials file failed. Reported error {:?}.",
+                    ap.creds.relays, e
+                );
+                return Err(Error::StorageFailure);
             }
         }
-        Err(_) => false,
+    } else {
+        debug!("Not creating or not updating credentials file with new relays.")
+    }
+
+    match err_count {
+        0 => Ok(()),
+        _ => Err(Error::AddRelayFailed),
     }
 }
 
-/// is this syntactically a valid relay string?
-pub(crate) fn is_relay_url(relay: &Url) -> bool {
-    if relay.scheme() != "wss" && relay.scheme() != "ws" {
-        return false;
-    } else if relay.host_str().is_none() {
-        return false;
+/// Check one relay on its own client, returns whether it could be
+/// connected, the report and our newest text note found on it.
+async fn check_relay(
+    ap: &Args,
+    keys: &Keys,
+    relay: &Relay,
+) -> (bool, Value, Option<nostr_sdk::Event>) {
+    let url = relay.url.clone();
+    let timeout = Duration::from_secs(ap.fetch_timeout);
+    let mut report = json!({ "url": url });
+    let client = Client::new(keys);
+    // the probe writes and reads, whatever the relay is used for
+    let relay = match route_relay(ap, relay) {
+        Ok(relay) => relay.with_flags(&[RelayFlag::Read, RelayFlag::Write]),
+        Err(ref e) => {
+            report["error"] = json!(e.to_string());
+            return (false, report, None);
+        }
+    };
+    if !add_relay(&client, &ap.bridges, &relay).await {
+        report["error"] = json!("Relay could not be added.");
+        return (false, report, None);
     }
-    true
+    let start = std::time::Instant::now();
+    let connected = match client.relay(&url).await {
+        Ok(r) => {
+            r.connect(Some(timeout)).await;
+            r.is_connected().await
+        }
+        Err(_) => false,
+    };
+    report["connected"] = json!(connected);
+    report["connect_latency_ms"] = json!(connected.then(|| start.elapsed().as_millis()));
+    // NIP-11 is served over HTTP, through the same proxy as the relay
+    let document = match relay_proxy_addr(&ap.bridges, &relay) {
+        Ok(proxy) => RelayInformationDocument::get(url.clone(), proxy).await.ok(),
+        Err(_) => None,
+    };
+    report["nip11"] = match document {
+        Some(doc) => json!({
+            "name": doc.name,
+            "description": doc.description,
+            "software": doc.software,
+            "version": doc.version,
+            "supported_nips": doc.supported_nips,
+            "limitation": doc.limitation,
+            "payment_required": doc.limitation.as_ref().and_then(|l| l.payment_required),
+            "payments_url": doc.payments_url,
+            "fees": doc.fees,
+        }),
+        None => Value::Null,
+    };
+    if !connected {
+        client.shutdown().await.ok();
+        return (false, report, None);
+    }
+    let test_event = EventBuilder::new(
+        Kind::from(RELAY_CHECK_KIND),
+        "nostr-commander relay check",
+        [],
+    )
+    .to_event(keys);
+    let (accepted, message) = match test_event {
+        Ok(event) => match client.send_event_to([url.clone()], event).await {
+            Ok(output) => (
+                output.success.contains(&url),
+                output.failed.get(&url).cloned().flatten(),
+            ),
+            Err(ref e) => (false, Some(e.to_string())),
+        },
+        Err(ref e) => (false, Some(e.to_string())),
+    };
+    report["test_event_accepted"] = json!(accepted);
+    report["test_event_message"] = json!(message);
+    let filter = Filter::new()
+        .author(keys.public_key())
+        .kind(Kind::TextNote)
+        .limit(1);
+    let last_note = match client
+        .get_events_from([url.clone()], vec![filter], Some(timeout))
+        .await
+    {
+        Ok(events) => events.into_iter().max_by_key(|e| e.created_at),
+        Err(ref e) => {
+            debug!("Fetching last note from {:?} failed. {:?}", url, e);
+            None
+        }
+    };
+    client.shutdown().await.ok();
+    (true, report, last_note)
 }
 
-/// Handle the --create_user CLI argument
-pub(crate) fn cli_create_user(ap: &mut Args) -> Result<(), Error> {
-    if !ap.create_user {
-        return Err(Error::MissingCliParameter);
+/// Handle the --check-relays CLI argument
+/// Report the health of each relay and offer to remove dead relays.
+pub(crate) async fn cli_check_relays(ap: &mut Args) -> Result<(), Error> {
+    let keys = Keys::parse(&ap.creds.secret_key_bech32)?;
+    let mut checks = Vec::new();
+    for relay in &ap.creds.relays {
+        debug!("Checking relay {:?}.", relay.url);
+        checks.push((relay.url.clone(), check_relay(ap, &keys, relay).await));
     }
-    if credentials_exist(ap) {
-        error!(concat!(
-            "Credentials file already exists. You have already created a user in ",
-            "the past. No --create-user is needed. Aborting. If you really want to create ",
-            "a new user, then delete the current user first with '--delete-user', or move ",
-            "or remove credentials file manually. ",
-            "Or just run your command again but without the '--create-user' option to ",
-            "use the currently existing user. ",
-        ));
-        return Err(Error::UserAlreadyExists);
+    // our last note is the newest note found on any relay
+    let newest = checks
+        .iter()
+        .filter_map(|(_, (_, _, note))| note.as_ref())
+        .max_by_key(|note| note.created_at)
+        .map(|note| note.id);
+    let mut dead: Vec<Url> = Vec::new();
+    for (url, (connected, mut report, note)) in checks {
+        report["last_note_id"] = json!(note.as_ref().map(|n| n.id));
+        report["has_last_note"] = json!(newest.is_some() && note.map(|n| n.id) == newest);
+        print_json(&report, ap.output, 0, "");
+        if !connected {
+            dead.push(url);
+        }
     }
-    match ap.name.as_ref() {
-        None => {
-            get_name(ap); // read from kb, put into metadata
+    if dead.is_empty() {
+        return Ok(());
+    }
+    let question = format!(
+        "Remove {} relays that could not be connected {:?} from the credentials file?",
+        dead.len(),
+        dead
+    );
+    if !ap.yes && !get_confirmation(&question) {
+        info!("Keeping relays that could not be connected.");
+        return Ok(());
+    }
+    ap.creds.relays.retain(|r| !dead.contains(&r.url));
+    info!("Removed relays {:?}.", dead);
+    if ap.nsec.is_none() {
+        if let Err(ref e) = ap.creds.save(get_credentials_actual_path(ap)) {
+            error!(
+                "Error: writing remaining relays to credentials file failed. Reported error {:?}.",
+                e
+            );
+            return Err(Error::StorageFailure);
         }
-        Some(n) => {
-            if n.trim().is_empty() {
-                ap.creds.metadata.name = None;
-            } else {
-                ap.creds.metadata.name = Some(n.trim().to_owned());
-            }
+    }
+    Ok(())
+}
+
+/// Build the relay list (NIP-65) of the relays
+fn relay_list_builder(relays: &[Relay]) -> EventBuilder {
+    EventBuilder::relay_list(
+        relays
+            .iter()
+            .filter_map(|r| r.relay_metadata().map(|m| (r.url.clone(), m))),
+    )
+}
+
+/// Handle the --publish-relay-list CLI argument
+/// Publish the relays of the credentials file as relay list (NIP-65).
+pub(crate) async fn cli_publish_relay_list(
+    client: &Client,
+    ap: &Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
+    let builder = relay_list_builder(&ap.creds.relays);
+    match send_builder_via_outbox(client, outbox, builder).await {
+        Ok(ref event_id) => {
+            debug!("Relay list published. event_id {:?}", event_id);
+            Ok(())
+        }
+        Err(ref e) => {
+            error!(
+                "Error: publishing relay list failed. Reported error {:?}.",
+                e
+            );
+            Err(Error::PublishFailed)
         }
     }
-    match ap.display_name.as_ref() {
-        None => {
-            get_display_name(ap); // read from kb, put into metadata
+}
+
+/// Handle the --import-relay-list CLI argument
+/// Fetch the relay list (NIP-65) of a user and add its relays.
+pub(crate) async fn cli_import_relay_list(client: &mut Client, ap: &mut Args) -> Result<(), Error> {
+    let key = ap.import_relay_list.clone().unwrap_or_default();
+    let pk = cstr_to_pubkey(ap, key.trim())?;
+    let filter = Filter::new().author(pk).kind(Kind::RelayList).limit(1);
+    let timeout = Some(Duration::from_secs(ap.fetch_timeout));
+    let event = match client
+        .get_events_of(vec![filter], EventSource::relays(timeout))
+        .await
+    {
+        Ok(events) => events.into_iter().max_by_key(|e| e.created_at),
+        Err(ref e) => {
+            error!(
+                "Error: fetching relay list of {:?} failed. Reported error {:?}.",
+                pk, e
+            );
+            return Err(Error::FetchFailed);
         }
-        Some(n) => {
-            if n.trim().is_empty() {
-                ap.creds.metadata.display_name = None;
-            } else {
-                ap.creds.metadata.display_name = Some(n.trim().to_owned());
-            }
+    };
+    let Some(event) = event else {
+        error!("Error: no relay list found for {:?}.", pk);
+        return Err(Error::FetchFailed);
+    };
+    let proxy_auth = get_proxy_auth(ap)?;
+    let mut err_count = 0usize;
+    for (url, metadata) in nip65::extract_relay_list(&event) {
+        if ap.creds.relays.iter().any(|r| &r.url == url) {
+            debug!("Relay {:?} exists already, keeping it as it is.", url);
+            continue;
+        }
+        if !is_relay_url(url) {
+            error!("Error: relay {:?} is not valid. Skipping it.", url);
+            err_count += 1;
+            continue;
+        }
+        let mut relay = Relay::from_relay_metadata(url.clone(), *metadata);
+        relay.proxy = ap.proxy;
+        relay.proxy_auth = proxy_auth.clone();
+        if !add_relay(client, &ap.bridges, &route_relay(ap, &relay)?).await {
+            err_count += 1;
+            continue;
+        }
+        if let Err(ref e) = client.connect_relay(url).await {
+            debug!("Connecting imported relay {:?} failed. {:?}", url, e);
+        }
+        print_json(&json!(relay), ap.output, 0, "");
+        ap.creds.relays.push(relay);
+    }
+    match err_count {
+        0 => Ok(()),
+        _ => Err(Error::AddRelayFailed),
+    }
+}
+
+/// Handle the --remove-relay CLI argument, remove CLI args contacts from creds data structure
+pub(crate) async fn cli_remove_relay(client: &Client, ap: &mut Args) -> Result<(), Error> {
+    let num = ap.remove_relay.len();
+    let mut i = 0;
+    while i < num {
+        ap.creds.relays.retain(|x| x.url != ap.remove_relay[i]);
+        i += 1;
+    }
+    Ok(())
+}
+
+fn trim_newline(s: &mut String) -> &mut String {
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
         }
     }
-    match ap.about.as_ref() {
-        None => {
-            get_about(ap); // read from kb, put into metadata
+    s
+}
+
+/// Fetch one event by id from the relays, and from the relay hints of an nevent.
+/// Returns the event and a relay it was seen on, to be used as relay hint.
+pub(crate) async fn fetch_event(
+    client: &Client,
+    ap: &Args,
+    pointer: &Nip19Event,
+) -> Result<(nostr_sdk::Event, Option<UncheckedUrl>), Error> {
+    let id = pointer.event_id;
+    let hints: Vec<Url> = pointer
+        .relays
+        .iter()
+        .filter_map(|r| Url::parse(r).ok())
+        .filter(is_relay_url)
+        .collect();
+    let temporary = RelayAccess::new(ap)
+        .connect(client, &hints, RelayFlag::Read)
+        .await;
+    let filter = Filter::new().id(id);
+    let timeout = Some(Duration::from_secs(ap.fetch_timeout));
+    let events = client
+        .get_events_of(vec![filter], EventSource::relays(timeout))
+        .await;
+    temporary.release(client).await;
+    let events = match events {
+        Ok(events) => events,
+        Err(ref e) => {
+            error!(
+                "Error: fetching event {:?} from relays failed. Reported error {:?}.",
+                id, e
+            );
+            return Err(Error::FetchEventFailed);
         }
-        Some(n) => {
-            if n.trim().is_empty() {
-                ap.creds.metadata.about = None;
-            } else {
-                ap.creds.metadata.about = Some(n.trim().to_owned());
+    };
+    match events.into_iter().find(|e| e.id == id) {
+        Some(event) => {
+            let relay = match client.database().event_seen_on_relays(&id).await {
+                Ok(Some(urls)) => urls.into_iter().next().map(UncheckedUrl::from),
+                _ => None,
             }
+            .or_else(|| hints.first().cloned().map(UncheckedUrl::from));
+            debug!("Fetched event {:?} seen on relay {:?}.", id, relay);
+            Ok((event, relay))
         }
-    }
-    match ap.picture.clone() {
         None => {
-            get_picture(ap); // read from kb, put into metadata
+            error!("Error: event {:?} not found on any relay.", id);
+            Err(Error::FetchEventFailed)
         }
-        Some(n) => {
-            if (n.scheme() == "none" || n.scheme() == "file")
-                && (n.path() == "/" || n.path() == "")
-                && n.host().is_none()
-            {
-                ap.creds.metadata.picture = None;
-            } else {
-                ap.creds.metadata.picture = Some(n.to_string());
+    }
+}
+
+/// Create an 'e' tag with marker as specified in NIP-10.
+fn marked_event_tag(event_id: EventId, relay_url: Option<UncheckedUrl>, marker: Marker) -> Tag {
+    Tag::from_standardized_without_cell(TagStandard::Event {
+        event_id,
+        relay_url,
+        marker: Some(marker),
+        public_key: None,
+    })
+}
+
+/// Build the NIP-10 tags for replying to the parent event.
+/// The root of the parent's thread and all participants of the parent are copied.
+/// `own` is never added as 'p' tag, one does not notify oneself.
+pub(crate) fn reply_tags(
+    parent: &nostr_sdk::Event,
+    relay: Option<UncheckedUrl>,
+    own: &PublicKey,
+) -> Vec<Tag> {
+    let mut root: Option<(EventId, Option<UncheckedUrl>)> = None;
+    let mut positional: Option<(EventId, Option<UncheckedUrl>)> = None;
+    for t in parent.tags.iter() {
+        if let Some(TagStandard::Event {
+            event_id,
+            relay_url,
+            marker,
+            ..
+        }) = t.as_standardized()
+        {
+            match marker {
+                Some(Marker::Root) if root.is_none() => root = Some((*event_id, relay_url.clone())),
+                // deprecated positional 'e' tags: the first one is the root
+                None if positional.is_none() => positional = Some((*event_id, relay_url.clone())),
+                _ => (),
             }
         }
     }
-    match ap.nip05.as_ref() {
-        None => {
-            get_nip05(ap); // read from kb, put into metadata
+    let mut tags: Vec<Tag> = Vec::new();
+    match root.or(positional) {
+        Some((root_id, root_relay)) => {
+            tags.push(marked_event_tag(root_id, root_relay, Marker::Root));
+            tags.push(marked_event_tag(parent.id, relay, Marker::Reply));
         }
-        Some(n) => {
-            if n.trim().is_empty() {
-                ap.creds.metadata.nip05 = None;
-            } else {
-                ap.creds.metadata.nip05 = Some(n.trim().to_owned());
-            }
+        // parent has no root, so parent is the root
+        None => tags.push(marked_event_tag(parent.id, relay, Marker::Root)),
+    }
+    let mut pubkeys: Vec<PublicKey> = vec![parent.pubkey];
+    for pkey in parent.public_keys() {
+        if !pubkeys.contains(pkey) {
+            pubkeys.push(*pkey);
         }
     }
-    info!("Metadata is: {:?}", ap.creds.metadata);
+    for pkey in pubkeys.into_iter().filter(|p| p != own) {
+        tags.push(Tag::public_key(pkey));
+    }
+    tags
+}
 
-    if ap.add_relay.is_empty() {
-        get_relays(ap);
-    } else {
-        let num = ap.add_relay.len();
-        let mut i = 0;
-        while i < num {
-            if is_relay_url(&ap.add_relay[i]) {
-                ap.creds
-                    .relays
-                    .push(Relay::new(ap.add_relay[i].clone(), ap.proxy));
-            } else {
-                error!(
-                    "Invalid relay syntax for relay {:?}. Skipping it.",
-                    ap.add_relay[i]
-                )
+/// Build tags and content suffix for --reply-to and --quote.
+/// Returns the tags to add and the text to append to each note.
+pub(crate) async fn cli_thread_tags(
+    client: &Client,
+    ap: &Args,
+) -> Result<(Vec<Tag>, String), Error> {
+    let own = str_to_pubkey(&ap.creds.public_key_bech32)?;
+    let mut tags: Vec<Tag> = Vec::new();
+    let mut suffix = String::new();
+    if let Some(id) = ap.reply_to.as_ref() {
+        let (parent, relay) = fetch_event(client, ap, &str_to_nevent(id.trim())?).await?;
+        tags.append(&mut reply_tags(&parent, relay, &own));
+    }
+    if let Some(id) = ap.quote.as_ref() {
+        let (quoted, relay) = fetch_event(client, ap, &str_to_nevent(id.trim())?).await?;
+        tags.push(marked_event_tag(quoted.id, relay.clone(), Marker::Mention));
+        if quoted.pubkey != own {
+            tags.push(Tag::public_key(quoted.pubkey));
+        }
+        let nevent = Nip19Event::new(quoted.id, relay.map(|r| r.to_string()));
+        match nevent.to_bech32() {
+            Ok(n) => suffix = format!("\n\nnostr:{}", n),
+            Err(ref e) => {
+                error!("Error: converting quoted event to nevent failed. {:?}", e);
+                return Err(Error::ConversionFailed);
             }
-            i += 1;
         }
     }
-    ap.creds.relays.dedup_by(|a, b| a.url == b.url);
+    Ok((tags, suffix))
+}
 
-    let my_keys: Keys = if ap.nsec.is_some() {
-        // nsec key provided as argument, import it
-        // parses from bech32 as well as from hex
-        debug!("Importing private key from --nsec argument");
-        Keys::new(SecretKey::parse(ap.nsec.clone().unwrap())?)
+/// The relays events are published to, i.e. those with the write flag
+async fn write_relay_urls(client: &Client) -> Vec<Url> {
+    client
+        .pool()
+        .relays_with_flag(RelayServiceFlags::WRITE, FlagCheck::All)
+        .await
+        .into_keys()
+        .collect()
+}
+
+/// Send a signed event to all write relays, and to the inbox relays of the
+/// people it is addressed to. If not all of our write relays accept it, the
+/// event is put into the outbox to be retried later.
+pub(crate) async fn send_via_outbox(
+    client: &Client,
+    outbox: &Outbox,
+    event: nostr_sdk::Event,
+) -> Result<EventId, Error> {
+    let own = write_relay_urls(client).await;
+    let extra = match outbox.routing {
+        Some(ref routing) => connect_inbox_relays(client, routing, &event, &own).await,
+        None => TemporaryRelays::default(),
+    };
+    let urls: Vec<Url> = own.iter().chain(extra.usable.iter()).cloned().collect();
+    let result = if extra.usable.is_empty() {
+        client.send_event(event.clone()).await
     } else {
-        // Generate new keys
-        debug!("A new private key is being generated for you");
-        Keys::generate()
+        client.send_event_to(urls.clone(), event.clone()).await
     };
-    debug!("Generated private key is: {:?}", my_keys.secret_key());
-    debug!("Generated public  key is: {:?}", my_keys.public_key());
-    match my_keys.public_key().to_bech32() {
-        Ok(k) => ap.creds.public_key_bech32 = k,
-        Err(ref e) => {
+    extra.release(client).await;
+    if let Ok(ref output) = result {
+        print_ok_messages(output, outbox.output);
+    }
+    let mut entry = OutboxEntry::new(event, &urls);
+    entry.record(&urls, result.as_ref().map_err(|e| e.to_string()));
+    outbox.sent.lock().unwrap().push(entry.clone());
+    let pending = entry.pending();
+    if !pending.is_empty() {
+        warn!(
+            "Event {:?} was not accepted by relays {:?}. It is kept in the outbox and will be retried.",
+            entry.event.id, pending
+        );
+    }
+    // the inbox relays were connected for this event only, they are not retried
+    if pending.iter().any(|url| own.contains(url)) {
+        if let Err(ref e) = outbox.queue(entry) {
             error!(
-                "Error: failed to convert public key. Aborting. Error reported: {:?}. ({:?})",
-                e, my_keys
+                "Error: queueing event in outbox failed. Reported error {:?}.",
+                e
             );
-            return Err(Error::KeyFailure);
         }
     }
-    match my_keys.secret_key().to_bech32() {
-        Ok(k) => ap.creds.secret_key_bech32 = k,
+    match result {
+        Ok(output) => Ok(output.val),
         Err(ref e) => {
-            error!(
-                "Error: failed to convert private key. Aborting. Error reported: {:?}. ({:?})",
-                e, my_keys
-            );
-            return Err(Error::KeyFailure);
+            error!("Error: sending event failed. Reported error {:?}.", e);
+            Err(Error::PublishFailed)
         }
     }
-    match ap.creds.save(get_credentials_actual_path(&ap)) {
-        Ok(()) => {
-            info!("Successfully stored credentials in credentials file {:?}. Protect it, it contains your private key. Data stored is {:?}.", get_credentials_actual_path(&ap), &ap.creds);
-        }
+}
+
+/// Print one '["OK", ...]' relay message per relay for json-spec output
+fn print_ok_messages(output: &nostr_sdk::pool::Output<EventId>, format: Output) {
+    let accepted = output.success.iter().map(|_| (true, String::new()));
+    let rejected = output
+        .failed
+        .values()
+        .map(|message| (false, message.clone().unwrap_or_default()));
+    for (status, message) in accepted.chain(rejected) {
+        let ok = RelayMessage::Ok {
+            event_id: output.val,
+            status,
+            message,
+        };
+        print_json_spec(&ok.as_json(), format);
+    }
+}
+
+/// Sign the event and send it, see send_via_outbox().
+pub(crate) async fn send_builder_via_outbox(
+    client: &Client,
+    outbox: &Outbox,
+    builder: EventBuilder,
+) -> Result<EventId, Error> {
+    match client.sign_event_builder(builder).await {
+        Ok(event) => send_via_outbox(client, outbox, event).await,
         Err(ref e) => {
-            error!(
-                "Error: failed to store credentials. Aborting. Error reported: {:?}. ({:?})",
-                e, my_keys
+            error!("Error: signing event failed. Reported error {:?}.", e);
+            Err(Error::PublishFailed)
+        }
+    }
+}
+
+/// Retry the events in the outbox on the relays that have not accepted
+/// them yet. Only events that are due are retried, unless forced.
+/// Events of other users, e.g. of a replaced key, are left alone.
+pub(crate) async fn flush_outbox(
+    client: &Client,
+    outbox: &Outbox,
+    force: bool,
+) -> Result<(), Error> {
+    let mut entries = outbox.load()?;
+    if entries.is_empty() {
+        debug!("Outbox is empty.");
+        return Ok(());
+    }
+    let own = match client.signer().await {
+        Ok(signer) => signer.public_key().await.ok(),
+        Err(_) => None,
+    };
+    let configured = write_relay_urls(client).await;
+    let now = Timestamp::now();
+    for entry in entries.iter_mut() {
+        if own != Some(entry.event.pubkey) {
+            warn!(
+                "Not retrying event {:?} from outbox, it was not created by the current user.",
+                entry.event.id
             );
-            return Err(Error::StorageFailure);
+            continue;
+        }
+        // relays that were removed will never acknowledge the event
+        entry
+            .relays
+            .retain(|r| r.accepted || configured.contains(&r.url));
+        let urls = entry.pending();
+        if urls.is_empty() || (!force && entry.next_attempt > now) {
+            continue;
         }
+        debug!(
+            "Retrying event {:?} from outbox on relays {:?}.",
+            entry.event.id, urls
+        );
+        let result = client
+            .send_event_to(urls.clone(), entry.event.clone())
+            .await;
+        entry.record(&urls, result.as_ref().map_err(|e| e.to_string()));
+    }
+    entries.retain(|e| !e.pending().is_empty());
+    outbox.save(&entries)?;
+    let remaining = entries
+        .iter()
+        .filter(|e| own == Some(e.event.pubkey))
+        .count();
+    if remaining == 0 {
+        info!("All events in the outbox have been relayed.");
+        Ok(())
+    } else if force {
+        error!(
+            "{} events in the outbox are still not accepted by all relays.",
+            remaining
+        );
+        Err(Error::OutboxFailed)
+    } else {
+        info!("{} events remain in the outbox.", remaining);
+        Ok(())
+    }
+}
+
+/// Handle the --show-outbox CLI argument
+pub(crate) fn cli_show_outbox(ap: &Args) -> Result<(), Error> {
+    let outbox = Outbox::new(get_outbox_path(ap));
+    for entry in outbox.load()? {
+        print_json(
+            &json!({
+                "event_id": entry.event.id,
+                "kind": entry.event.kind,
+                "created_at": entry.event.created_at.to_human_datetime(),
+                "attempts": entry.attempts,
+                "next_attempt": entry.next_attempt.to_human_datetime(),
+                "relays": entry.relays,
+            }),
+            ap.output,
+            0,
+            "",
+        );
     }
     Ok(())
 }
 
-/// Add relays to from Credentials to client
-pub(crate) async fn add_relays_from_creds(client: &mut Client, ap: &mut Args) -> Result<(), Error> {
-    let mut err_count = 0u32;
-    for relay in &ap.creds.relays {
-        match add_relay(client, &relay.url, relay.proxy).await {
-            false => err_count += 1,
-            _ => (),
+/// Handle the --wait-ok CLI argument
+/// Print the status of each event sent in this run on each relay.
+/// Fails if an event was accepted by fewer relays than the quorum.
+pub(crate) fn cli_wait_ok(ap: &Args, outbox: &Outbox) -> Result<(), Error> {
+    let mut below_quorum = 0usize;
+    for entry in outbox.sent.lock().unwrap().iter() {
+        let accepted = entry.relays.iter().filter(|r| r.accepted).count();
+        if accepted < ap.quorum {
+            below_quorum += 1;
         }
+        print_json(
+            &json!({
+                "event_id": entry.event.id,
+                "kind": entry.event.kind,
+                "accepted": accepted,
+                "not_accepted": entry.relays.len() - accepted,
+                "quorum": ap.quorum,
+                "quorum_reached": accepted >= ap.quorum,
+                "relays": entry.relays,
+            }),
+            ap.output,
+            0,
+            "",
+        );
     }
-    match err_count {
-        0 => Ok(()),
-        _ => Err(Error::AddRelayFailed),
+    if below_quorum != 0 {
+        error!(
+            "{} events were accepted by fewer than {} relays.",
+            below_quorum, ap.quorum
+        );
+        Err(Error::QuorumNotReached)
+    } else {
+        Ok(())
     }
 }
 
-async fn add_relay(client: &mut Client, url: &Url, proxy: Option<SocketAddr>) -> bool {
-    let mut result = Ok(true);
-    match proxy {
-        None => {
-            result = client.add_relay(url).await;
+/// Gift wrap (NIP-59) a rumor for the receiver. Like Client::gift_wrap,
+/// but the event is returned so that it can be kept in the outbox.
+async fn gift_wrap_event(
+    client: &Client,
+    receiver: &PublicKey,
+    rumor: &UnsignedEvent,
+) -> Result<nostr_sdk::Event, Error> {
+    let content = match client.signer().await {
+        Ok(signer) => signer.nip44_encrypt(receiver, rumor.as_json()).await,
+        Err(ref e) => {
+            error!("Error: signer reported error {:?}.", e);
+            return Err(Error::DmFailed);
+        }
+    };
+    let content = match content {
+        Ok(content) => content,
+        Err(ref e) => {
+            error!("Error: nip44_encrypt reported error {:?}.", e);
+            return Err(Error::DmFailed);
+        }
+    };
+    let seal = EventBuilder::new(Kind::Seal, content, [])
+        .custom_created_at(Timestamp::tweaked(nip59::RANGE_RANDOM_TIMESTAMP_TWEAK));
+    let seal = match client.sign_event_builder(seal).await {
+        Ok(seal) => seal,
+        Err(ref e) => {
+            error!("Error: signing seal reported error {:?}.", e);
+            return Err(Error::DmFailed);
         }
-        Some(addr) => {
-            let relaypool = client.pool();
-            let mode = ConnectionMode::Proxy(addr);
-            let opts = RelayOptions::new()
-                .connection_mode(mode)
-                .write(false)
-                .retry_sec(11);
-            let result = relaypool.add_relay(url, opts).await;
+    };
+    match EventBuilder::gift_wrap_from_seal(receiver, &seal, None) {
+        Ok(gift_wrap) => Ok(gift_wrap),
+        Err(ref e) => {
+            error!("Error: gift_wrap_from_seal reported error {:?}.", e);
+            Err(Error::DmFailed)
         }
     }
-    match result {
-        Ok(value) => {
-            let status = if value { "successful" } else { "already added" };
+}
+
+/// Build a reaction (NIP-25) to the event, including a relay hint.
+pub(crate) fn reaction_builder(
+    event: &nostr_sdk::Event,
+    relay: Option<UncheckedUrl>,
+    reaction: &str,
+) -> EventBuilder {
+    // NIP-25 puts the author right after the relay hint, without a marker
+    let relay = relay.map(|r| r.to_string()).unwrap_or_default();
+    let tags = [
+        Tag::custom(
+            TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::E)),
+            [event.id.to_hex(), relay, event.pubkey.to_hex()],
+        ),
+        Tag::public_key(event.pubkey),
+        Tag::from_standardized_without_cell(TagStandard::Kind(event.kind)),
+    ];
+    EventBuilder::new(Kind::Reaction, reaction, tags)
+}
+
+/// Handle the --react CLI argument
+/// Publish a reaction to one event.
+pub(crate) async fn cli_react(client: &Client, ap: &Args, outbox: &Outbox) -> Result<(), Error> {
+    let pointer = str_to_nevent(ap.react[0].trim())?;
+    let id = pointer.event_id;
+    let reaction = match ap.react.get(1) {
+        Some(r) if !r.trim().is_empty() => r.trim(),
+        _ => "+",
+    };
+    let (event, relay) = fetch_event(client, ap, &pointer).await?;
+    match send_builder_via_outbox(client, outbox, reaction_builder(&event, relay, reaction)).await {
+        Ok(ref event_id) => {
             debug!(
-                "add_relay...() with relay {:?} with proxy {:?}: {}.",
-                url, proxy, status
+                "Reaction {:?} to event {:?} sent successfully. event_id {:?}",
+                reaction, id, event_id
             );
-            true
+            Ok(())
         }
         Err(ref e) => {
             error!(
-                "Error: add_relay...() returned error. Relay {:?} not added. \
-                    Reported error {:?}.",
-                url, e
+                "Reaction {:?} to event {:?} failed. Reported error {:?}",
+                reaction, id, e
             );
-            false
+            Err(Error::ReactFailed)
         }
     }
 }
 
-/// Handle the --add_relay CLI argument.
-/// Add relays from --add-relay.
-pub(crate) async fn cli_add_relay(client: &mut Client, ap: &mut Args) -> Result<(), Error> {
-    let mut err_count = 0u32;
-    let urls: Vec<&Url> = ap
-        .add_relay
-        .iter()
-        .filter_map(|url| match is_relay_url(url) {
-            true => Some(url),
-            false => {
+/// Handle the --repost CLI argument
+/// Publish reposts of events.
+pub(crate) async fn cli_repost(client: &Client, ap: &Args, outbox: &Outbox) -> Result<(), Error> {
+    let mut err_count = 0usize;
+    for s in &ap.repost {
+        let pointer = match str_to_nevent(s.trim()) {
+            Ok(pointer) => pointer,
+            Err(_) => {
                 err_count += 1;
-                error!(
-                    "Error: Relay {:?} is syntactically not correct. Relay not added.",
-                    url,
-                );
-                None
+                continue;
+            }
+        };
+        let id = pointer.event_id;
+        let (event, relay) = match fetch_event(client, ap, &pointer).await {
+            Ok(r) => r,
+            Err(_) => {
+                err_count += 1;
+                continue;
+            }
+        };
+        match send_builder_via_outbox(client, outbox, EventBuilder::repost(&event, relay)).await {
+            Ok(ref event_id) => debug!(
+                "Repost of event {:?} sent successfully. event_id {:?}",
+                id, event_id
+            ),
+            Err(ref e) => {
+                err_count += 1;
+                error!("Repost of event {:?} failed. Reported error {:?}", id, e);
+            }
+        }
+    }
+    if err_count != 0 {
+        Err(Error::RepostFailed)
+    } else {
+        Ok(())
+    }
+}
+
+/// Handle the --publish CLI argument
+/// Publish notes.
+pub(crate) async fn cli_publish(
+    client: &Client,
+    ap: &mut Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
+    let mut tags = cli_tags(ap)?;
+    let (mut thread_tags, suffix) = cli_thread_tags(client, ap).await?;
+    tags.append(&mut thread_tags);
+    let mut err_count = 0usize;
+    let num = ap.publish.len();
+    let mut i = 0;
+    while i < num {
+        let note = &ap.publish[i];
+        trace!("publish: {:?}", note);
+        if note.is_empty() {
+            info!("Skipping empty text note.");
+            i += 1;
+            continue;
+        };
+        if note == "--" {
+            info!("Skipping '--' text note as these are used to separate arguments.");
+            i += 1;
+            continue;
+        };
+        // - map to - (stdin pipe)
+        // \- maps to text r'-', a 1-letter message
+        let fnote = if note == r"-" {
+            let mut line = String::new();
+            if atty::is(Stream::Stdin) {
+                print!("Message: ");
+                std::io::stdout()
+                    .flush()
+                    .expect("error: could not flush stdout");
+                io::stdin().read_line(&mut line)?;
+            } else {
+                io::stdin().read_to_string(&mut line)?;
+            }
+            line
+        } else if note == r"_" {
+            let mut eof = false;
+            while !eof {
+                let mut line = String::new();
+                match io::stdin().read_line(&mut line) {
+                    // If this function returns Ok(0), the stream has reached EOF.
+                    Ok(n) => {
+                        if n == 0 {
+                            eof = true;
+                            debug!("Reached EOF of pipe stream.");
+                        } else {
+                            debug!(
+                                "Read {n} bytes containing \"{}\\n\" from pipe stream.",
+                                trim_newline(&mut line.clone())
+                            );
+                            if !suffix.is_empty() {
+                                line = trim_newline(&mut line).to_string() + &suffix;
+                            }
+                            match send_builder_via_outbox(client, outbox, EventBuilder::text_note(&line, tags.clone())).await {
+                                Ok(ref event_id) => debug!(
+                                    "Publish_text_note number {:?} from pipe stream sent successfully. {:?}. event_id {:?}",
+                                    i, &line, event_id
+                                ),
+                                Err(ref e) => {
+                                    err_count += 1;
+                                    error!(
+                                        "Publish_text_note number {:?} from pipe stream failed. {:?}",
+                                        i, &line
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    Err(ref e) => {
+                        err_count += 1;
+                        error!("Error: reading from pipe stream reported {}", e);
+                    }
+                }
             }
-        })
-        .collect();
-
-    for url in urls {
-        match add_relay(client, url, ap.proxy).await {
-            true => ap.creds.relays.push(Relay::new(url.clone(), ap.proxy)),
-            false => err_count += 1,
+            "".to_owned()
+        } else if note == r"\-" {
+            "-".to_string()
+        } else if note == r"\_" {
+            "_".to_string()
+        } else if note == r"\-\-" {
+            "--".to_string()
+        } else if note == r"\-\-\-" {
+            "---".to_string()
+        } else {
+            note.to_string()
+        };
+        if fnote.is_empty() {
+            info!("Skipping empty text note.");
+            i += 1;
+            continue;
         }
-    }
 
-    if ap.nsec.is_none() || ap.create_user {
-        debug!("Creating or updating credentials file to add new relays.");
-        match ap.creds.save(get_credentials_actual_path(ap)) {
-            Ok(()) => {
-                debug!(
-                    "writing new relays {:?} to credentials file successful.",
-                    ap.creds.relays
-                );
-            }
+        let fnote = fnote + &suffix;
+        match send_builder_via_outbox(
+            client,
+            outbox,
+            EventBuilder::text_note(&fnote, tags.clone()),
+        )
+        .await
+        {
+            Ok(ref event_id) => debug!(
+                "Publish_text_note number {:?} sent successfully. {:?}, event_id {:?}",
+                i, &fnote, event_id
+            ),
             Err(ref e) => {
-                error!(
-                    "Error: writing new relays {:?} to credentials file failed. Reported error {:?}.",
-                    ap.creds.relays, e
-                );
                 err_count += 1;
+                error!("Publish_text_note number {:?} failed. {:?}", i, &fnote);
             }
         }
-    } else {
-        debug!("Not creating or not updating credentials file with new relays.")
+        i += 1;
     }
-
-    match err_count {
-        0 => Ok(()),
-        _ => Err(Error::AddRelayFailed),
+    if err_count != 0 {
+        Err(Error::PublishFailed)
+    } else {
+        Ok(())
     }
 }
 
-/// Handle the --remove-relay CLI argument, remove CLI args contacts from creds data structure
-pub(crate) async fn cli_remove_relay(client: &Client, ap: &mut Args) -> Result<(), Error> {
-    let num = ap.remove_relay.len();
-    let mut i = 0;
-    while i < num {
-        ap.creds.relays.retain(|x| x.url != ap.remove_relay[i]);
-        i += 1;
+/// Mine a proof-of-work nonce (NIP-13) on all CPU cores.
+/// Returns the nonce tag that gives the event id at least `difficulty` leading zero bits.
+pub(crate) fn mine_pow_nonce(
+    pubkey: &PublicKey,
+    created_at: &Timestamp,
+    kind: &Kind,
+    tags: &[Tag],
+    content: &str,
+    difficulty: u8,
+) -> Result<Tag, Error> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1) as u128;
+    let found = std::sync::atomic::AtomicBool::new(false);
+    let result: std::sync::Mutex<Option<Tag>> = std::sync::Mutex::new(None);
+    debug!(
+        "Mining nonce with difficulty {} on {} threads.",
+        difficulty, threads
+    );
+    std::thread::scope(|scope| {
+        for t in 0..threads {
+            let found = &found;
+            let result = &result;
+            scope.spawn(move || {
+                // each thread tries every n-th nonce, n being the number of threads
+                let mut nonce = t;
+                let mut tags = tags.to_vec();
+                while !found.load(std::sync::atomic::Ordering::Relaxed) {
+                    tags.push(Tag::pow(nonce, difficulty));
+                    let id = EventId::new(pubkey, created_at, kind, &tags, content);
+                    if id.check_pow(difficulty) {
+                        if !found.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                            *result.lock().unwrap() = tags.pop();
+                        }
+                        break;
+                    }
+                    tags.pop();
+                    nonce += threads;
+                }
+            });
+        }
+    });
+    match result.into_inner() {
+        Ok(Some(nonce)) => Ok(nonce),
+        _ => Err(Error::PublishPowFailed),
     }
-    Ok(())
 }
 
-fn trim_newline(s: &mut String) -> &mut String {
-    if s.ends_with('\n') {
-        s.pop();
-        if s.ends_with('\r') {
-            s.pop();
-        }
-    }
-    return s;
+/// Mine and publish one text note with proof-of-work.
+async fn publish_pow_text_note(
+    client: &Client,
+    outbox: &Outbox,
+    pubkey: PublicKey,
+    content: &str,
+    tags: &[Tag],
+    difficulty: u8,
+) -> Result<EventId, Error> {
+    let created_at = Timestamp::now();
+    let mut tags = tags.to_vec();
+    if difficulty > 0 {
+        let content = content.to_string();
+        let ptags = tags.clone();
+        let start = std::time::Instant::now();
+        // mining is CPU bound, keep it away from the async runtime
+        let nonce = tokio::task::spawn_blocking(move || {
+            mine_pow_nonce(
+                &pubkey,
+                &created_at,
+                &Kind::TextNote,
+                &ptags,
+                &content,
+                difficulty,
+            )
+        })
+        .await
+        .map_err(|e| {
+            error!("Error: mining nonce failed. Reported error {:?}.", e);
+            Error::PublishPowFailed
+        })??;
+        debug!("Mined nonce {:?} in {:?}.", nonce, start.elapsed());
+        tags.push(nonce);
+    }
+    // the event id only depends on the fields above, so signing keeps the mined id
+    let builder = EventBuilder::text_note(content, tags).custom_created_at(created_at);
+    send_builder_via_outbox(client, outbox, builder)
+        .await
+        .map_err(|_| Error::PublishPowFailed)
 }
 
-/// Handle the --publish CLI argument
-/// Publish notes.
-pub(crate) async fn cli_publish(client: &Client, ap: &mut Args) -> Result<(), Error> {
+/// Handle the --publish-pow CLI argument
+/// Publish notes with POW.
+pub(crate) async fn cli_publish_pow(
+    client: &Client,
+    ap: &mut Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
+    let mut tags = cli_tags(ap)?;
+    let (mut thread_tags, suffix) = cli_thread_tags(client, ap).await?;
+    tags.append(&mut thread_tags);
+    let pubkey = str_to_pubkey(&ap.creds.public_key_bech32)?;
+    let difficulty = ap.pow_difficulty;
+    if difficulty > POW_DIFFICULTY_MAX {
+        error!(
+            "Error: POW difficulty {} is too high, the highest is {}.",
+            difficulty, POW_DIFFICULTY_MAX
+        );
+        return Err(Error::UnsupportedCliParameter(
+            "--pow-difficulty is limited to 32",
+        ));
+    }
     let mut err_count = 0usize;
-    let num = ap.publish.len();
+    let num = ap.publish_pow.len();
     let mut i = 0;
     while i < num {
-        let note = &ap.publish[i];
-        trace!("publish: {:?}", note);
+        let note = &ap.publish_pow[i];
+        trace!("publish_pow: {:?}", note);
         if note.is_empty() {
             info!("Skipping empty text note.");
             i += 1;
@@ -1930,15 +4941,20 @@ pub(crate) async fn cli_publish(client: &Client, ap: &mut Args) -> Result<(), Er
                                 "Read {n} bytes containing \"{}\\n\" from pipe stream.",
                                 trim_newline(&mut line.clone())
                             );
-                            match client.publish_text_note(&line, []).await {
+                            if !suffix.is_empty() {
+                                line = trim_newline(&mut line).to_string() + &suffix;
+                            }
+                            match publish_pow_text_note(client, outbox, pubkey, &line, &tags, difficulty)
+                                .await
+                            {
                                 Ok(ref event_id) => debug!(
-                                    "Publish_text_note number {:?} from pipe stream sent successfully. {:?}. event_id {:?}",
+                                    "Publish_pow_text_note number {:?} from pipe stream sent successfully. {:?}. event_id {:?}",
                                     i, &line, event_id
                                 ),
                                 Err(ref e) => {
                                     err_count += 1;
                                     error!(
-                                        "Publish_text_note number {:?} from pipe stream failed. {:?}",
+                                        "Publish_pow_text_note number {:?} from pipe stream failed. {:?}",
                                         i, &line
                                     );
                                 }
@@ -1969,32 +4985,196 @@ pub(crate) async fn cli_publish(client: &Client, ap: &mut Args) -> Result<(), Er
             continue;
         }
 
-        match client.publish_text_note(&fnote, []).await {
+        let fnote = fnote + &suffix;
+        match publish_pow_text_note(client, outbox, pubkey, &fnote, &tags, difficulty).await {
             Ok(ref event_id) => debug!(
-                "Publish_text_note number {:?} sent successfully. {:?}, event_id {:?}",
+                "Publish_pow_text_note number {:?} sent successfully. {:?}, event_id {:?}",
                 i, &fnote, event_id
             ),
             Err(ref e) => {
                 err_count += 1;
-                error!("Publish_text_note number {:?} failed. {:?}", i, &fnote);
+                error!("Publish_pow_text_note number {:?} failed. {:?}", i, &fnote);
             }
         }
         i += 1;
     }
     if err_count != 0 {
-        Err(Error::PublishFailed)
+        Err(Error::PublishPowFailed)
     } else {
         Ok(())
     }
 }
 
+/// Send one DM with the given extra tags, either gift-wrapped (NIP-17)
+/// or encrypted (NIP-04) depending on the protocol.
+/// With NIP-17 a copy is also gift-wrapped for ourselves.
+async fn send_dm(
+    client: &Client,
+    outbox: &Outbox,
+    recipient: PublicKey,
+    msg: &str,
+    tags: &[Tag],
+    protocol: DmProtocol,
+) -> Result<EventId, Error> {
+    match protocol {
+        DmProtocol::Nip17 => {
+            // tags go into the rumor, i.e. they are encrypted as well
+            let own = match client.signer().await {
+                Ok(signer) => signer.public_key().await.ok(),
+                Err(_) => None,
+            };
+            let rumor = EventBuilder::private_msg_rumor(recipient, msg, None)
+                .add_tags(tags.to_vec())
+                .to_unsigned_event(own.ok_or(Error::DmFailed)?);
+            let gift_wrap = gift_wrap_event(client, &recipient, &rumor).await?;
+            let event_id = send_via_outbox(client, outbox, gift_wrap)
+                .await
+                .map_err(|_| Error::DmFailed)?;
+            // a copy wrapped for ourselves keeps the sent DM readable, e.g. for --dm-history
+            if let Some(own) = own.filter(|own| own != &recipient) {
+                let own_copy = match gift_wrap_event(client, &own, &rumor).await {
+                    Ok(gift_wrap) => send_via_outbox(client, outbox, gift_wrap).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(ref e) = own_copy {
+                    warn!("Warning: gift_wrap of own copy reported error {:?}.", e);
+                }
+            }
+            Ok(event_id)
+        }
+        DmProtocol::Nip04 => {
+            let encrypted = match client.signer().await {
+                Ok(signer) => signer.nip04_encrypt(&recipient, msg).await,
+                Err(ref e) => {
+                    error!("Error: signer reported error {:?}.", e);
+                    return Err(Error::DmFailed);
+                }
+            };
+            let encrypted = match encrypted {
+                Ok(encrypted) => encrypted,
+                Err(ref e) => {
+                    error!("Error: nip04_encrypt reported error {:?}.", e);
+                    return Err(Error::DmFailed);
+                }
+            };
+            let builder = EventBuilder::new(
+                Kind::EncryptedDirectMessage,
+                encrypted,
+                [Tag::public_key(recipient)],
+            )
+            .add_tags(tags.to_vec());
+            send_builder_via_outbox(client, outbox, builder)
+                .await
+                .map_err(|_| Error::DmFailed)
+        }
+    }
+}
+
+/// A received DM after decryption, independent of the protocol used.
+pub(crate) struct DecryptedDm {
+    sender: PublicKey,
+    recipient: Option<PublicKey>,
+    content: String,
+    created_at: Timestamp,
+    protocol: DmProtocol,
+}
+
+/// Decrypt a NIP-04 DM (kind 4) or unwrap a NIP-17 DM (kind 1059).
+/// The DM can be addressed to us or be sent by us.
+pub(crate) async fn decrypt_dm(
+    client: &Client,
+    event: &nostr_sdk::Event,
+) -> Result<DecryptedDm, Error> {
+    let signer = match client.signer().await {
+        Ok(signer) => signer,
+        Err(ref e) => {
+            debug!("Error: signer reported error {:?}.", e);
+            return Err(Error::DecryptDmFailed);
+        }
+    };
+    match event.kind {
+        Kind::EncryptedDirectMessage => {
+            let recipient = event.public_keys().next().copied();
+            let own = signer.public_key().await.ok();
+            // the shared secret is computed with the other party
+            let peer = if Some(event.pubkey) == own {
+                recipient.ok_or(Error::DecryptDmFailed)?
+            } else {
+                event.pubkey
+            };
+            match signer.nip04_decrypt(&peer, &event.content).await {
+                Ok(content) => Ok(DecryptedDm {
+                    sender: event.pubkey,
+                    recipient,
+                    content,
+                    created_at: event.created_at,
+                    protocol: DmProtocol::Nip04,
+                }),
+                Err(ref e) => {
+                    debug!("Error: nip04_decrypt reported error {:?}.", e);
+                    Err(Error::DecryptDmFailed)
+                }
+            }
+        }
+        Kind::GiftWrap => match signer.unwrap_gift_wrap(event).await {
+            Ok(gift) if gift.rumor.kind == Kind::PrivateDirectMessage => Ok(DecryptedDm {
+                sender: gift.sender,
+                recipient: gift
+                    .rumor
+                    .tags
+                    .iter()
+                    .find_map(|t| match t.as_standardized() {
+                        Some(TagStandard::PublicKey { public_key, .. }) => Some(*public_key),
+                        _ => None,
+                    }),
+                content: gift.rumor.content,
+                created_at: gift.rumor.created_at,
+                protocol: DmProtocol::Nip17,
+            }),
+            Ok(gift) => {
+                debug!("Gift wrap contains kind {:?}, not a DM.", gift.rumor.kind);
+                Err(Error::DecryptDmFailed)
+            }
+            Err(ref e) => {
+                debug!("Error: unwrap_gift_wrap reported error {:?}.", e);
+                Err(Error::DecryptDmFailed)
+            }
+        },
+        _ => Err(Error::DecryptDmFailed),
+    }
+}
+
+/// Is the DM one we sent to somebody else?
+pub(crate) fn is_sent_dm(ap: &Args, dm: &DecryptedDm) -> bool {
+    let own = PublicKey::from_bech32(&ap.creds.public_key_bech32).ok();
+    own == Some(dm.sender) && dm.recipient != own
+}
+
+/// Json describing a decrypted DM, used for printing.
+pub(crate) fn dm_json(ap: &Args, dm: &DecryptedDm) -> Value {
+    json!({
+        "event_type": "DM",
+        "event_type_meaning": "Private DM was decrypted.",
+        "protocol": dm.protocol.to_string(),
+        "created_at": dm.created_at.to_human_datetime(),
+        "from_alias": get_contact_alias_or_keystr_by_key(ap, dm.sender),
+        "from_pubkey": dm.sender,
+        "to_alias": dm.recipient.map(|pk| get_contact_alias_or_keystr_by_key(ap, pk)),
+        "to_pubkey": dm.recipient,
+        "content": dm.content,
+    })
+}
+
 /// Publish DMs.
 pub(crate) async fn send_dms(
     client: &Client,
+    outbox: &Outbox,
     notes: &[String],
     recipient: PublicKey,
+    tags: &[Tag],
+    protocol: DmProtocol,
 ) -> Result<(), Error> {
-    trace!("send_dms: {:?} {:?}", notes, recipient);
+    trace!("send_dms: {:?} {:?} {:?}", notes, recipient, protocol);
     let mut err_count = 0usize;
     let num = notes.len();
     let mut i = 0;
@@ -2040,8 +5220,7 @@ pub(crate) async fn send_dms(
                                 "Read {n} bytes containing \"{}\\n\" from pipe stream.",
                                 trim_newline(&mut line.clone())
                             );
-                            // was send_direct_msg: Unsecure! Use `send_private_msg` instead.
-                            match client.send_private_msg(recipient, &line, None).await {
+                            match send_dm(client, outbox, recipient, &line, tags, protocol).await {
                                 Ok(event_id) => debug!(
                                     "send_private_msg number {:?} from pipe stream sent successfully. {:?}, sent to {:?}, event_id {:?}",
                                     i, &line, recipient, event_id
@@ -2080,8 +5259,7 @@ pub(crate) async fn send_dms(
             continue;
         }
 
-        // was send_direct_msg: Unsecure! Use `send_private_msg` instead.
-        match client.send_private_msg(recipient, &fnote, None).await {
+        match send_dm(client, outbox, recipient, &fnote, tags, protocol).await {
             Ok(ref event_id) => debug!(
                 "DM message number {:?} sent successfully. {:?}, sent to {:?}, event_id {:?}.",
                 i, &fnote, recipient, event_id
@@ -2097,23 +5275,102 @@ pub(crate) async fn send_dms(
         i += 1;
     }
     if err_count != 0 {
-        Err(Error::PublishPowFailed)
+        Err(Error::DmFailed)
     } else {
         Ok(())
     }
 }
 
+/// Handle the --dm-history CLI argument
+/// Print the DMs exchanged with one user in chronological order.
+pub(crate) async fn cli_dm_history(client: &Client, ap: &Args) -> Result<(), Error> {
+    let keystr = ap.dm_history.as_deref().unwrap_or_default().trim();
+    let peer = cstr_to_pubkey(ap, keystr)?;
+    let own = str_to_pubkey(&ap.creds.public_key_bech32)?;
+    let mut since: Option<Timestamp> = None;
+    if ap.limit_days != 0 {
+        since = Some(Timestamp::now() - Duration::new(ap.limit_days * 24 * 60 * 60, 0));
+    }
+    if ap.limit_hours != 0 {
+        since = Some(Timestamp::now() - Duration::new(ap.limit_hours * 60 * 60, 0));
+    }
+    let mut nip04_sent = Filter::new()
+        .kind(Kind::EncryptedDirectMessage)
+        .author(own)
+        .pubkey(peer);
+    let mut nip04_received = Filter::new()
+        .kind(Kind::EncryptedDirectMessage)
+        .author(peer)
+        .pubkey(own);
+    // gift wraps carry random authors, sent DMs are found via the copy wrapped for us
+    let mut nip17 = Filter::new().kind(Kind::GiftWrap).pubkey(own);
+    if let Some(since) = since {
+        nip04_sent = nip04_sent.since(since);
+        nip04_received = nip04_received.since(since);
+        nip17 = nip17.since(since - Duration::from_secs(GIFT_WRAP_TWEAK));
+    }
+    // the newest n of each direction cover the newest n of the conversation,
+    // gift wraps cannot be limited as most of them are not from the peer
+    if ap.limit_number != 0 {
+        nip04_sent = nip04_sent.limit(ap.limit_number);
+        nip04_received = nip04_received.limit(ap.limit_number);
+    }
+    let timeout = Some(Duration::from_secs(ap.fetch_timeout));
+    let events = match client
+        .get_events_of(
+            vec![nip04_sent, nip04_received, nip17],
+            EventSource::relays(timeout),
+        )
+        .await
+    {
+        Ok(events) => events,
+        Err(ref e) => {
+            error!(
+                "Error: fetching DMs from relays failed. Reported error {:?}.",
+                e
+            );
+            return Err(Error::DmHistoryFailed);
+        }
+    };
+    debug!("Fetched {} DM events.", events.len());
+    let mut dms: Vec<DecryptedDm> = Vec::new();
+    for event in &events {
+        match decrypt_dm(client, event).await {
+            Ok(dm) => {
+                let sent = dm.sender == own && dm.recipient == Some(peer);
+                let received = dm.sender == peer && dm.recipient == Some(own);
+                if (sent || received) && since.is_none_or(|s| dm.created_at >= s) {
+                    dms.push(dm);
+                }
+            }
+            Err(ref e) => debug!(
+                "DM {:?} could not be decrypted. Reported error is: {:?}",
+                event.id, e
+            ),
+        }
+    }
+    dms.sort_by_key(|dm| dm.created_at);
+    if ap.limit_number != 0 && dms.len() > ap.limit_number {
+        dms.drain(..dms.len() - ap.limit_number);
+    }
+    for dm in &dms {
+        print_json(&dm_json(ap, dm), ap.output, 0, "");
+    }
+    Ok(())
+}
+
 /// Handle the --dm CLI argument
 /// Publish DMs.
-pub(crate) async fn cli_dm(client: &Client, ap: &mut Args) -> Result<(), Error> {
+pub(crate) async fn cli_dm(client: &Client, ap: &mut Args, outbox: &Outbox) -> Result<(), Error> {
     let num = ap.dm.len();
     if num < 2 {
         return Err(Error::MissingCliParameter);
     }
+    let tags = cli_tags(ap)?;
     match cstr_to_pubkey(ap, ap.dm[0].trim()) {
         Ok(pk) => {
             let notes = &ap.dm[1..];
-            send_dms(client, notes, pk).await
+            send_dms(client, outbox, notes, pk, &tags, ap.dm_protocol).await
         }
         Err(ref e) => {
             error!(
@@ -2128,27 +5385,28 @@ pub(crate) async fn cli_dm(client: &Client, ap: &mut Args) -> Result<(), Error>
 
 async fn send_channel_message(
     client: &Client,
+    outbox: &Outbox,
     channel_id: &PublicKey,
     relay_url: &Url,
     line: &str,
+    tags: &[Tag],
     annotation: &str,
 ) -> bool {
-    let tags: Vec<Tag> = vec![]; // TODO: add relay_url tag
+    let id_tags: Vec<Tag> = vec![]; // TODO: add relay_url tag
     let event_id = EventId::new(
         &channel_id.clone(),
         &Timestamp::now(),
         &Kind::ChannelMessage,
-        &tags,
+        &id_tags,
         line,
     );
     //created_at: &Timestamp,
     //kind: &Kind,
     //tags: &[Tag],
     //content: &str,
-    match client
-        .send_channel_msg(event_id, relay_url.clone(), line)
-        .await
-    {
+    let builder =
+        EventBuilder::channel_msg(event_id, relay_url.clone(), line).add_tags(tags.to_vec());
+    match send_builder_via_outbox(client, outbox, builder).await {
         Ok(ref event_id) => {
             debug!(
                 "send_channel_msg number {} sent successfully. {:?}, sent to {:?}, event_id is {:?}",
@@ -2169,9 +5427,11 @@ async fn send_channel_message(
 /// Send messages to one channel.
 pub(crate) async fn send_channel_messages(
     client: &Client,
+    outbox: &Outbox,
     notes: &[String], // msgs
     channel_id: PublicKey,
     relay_url: Url,
+    tags: &[Tag],
 ) -> Result<(), Error> {
     trace!("send_channel_messages {:?} {:?}.", notes, channel_id);
     let mut err_count = 0usize;
@@ -2219,17 +5479,18 @@ pub(crate) async fn send_channel_messages(
                                 "Read {n} bytes containing \"{}\\n\" from pipe stream.",
                                 trim_newline(&mut line.clone())
                             );
-                            match send_channel_message(
+                            if !send_channel_message(
                                 client,
+                                outbox,
                                 &channel_id,
                                 &relay_url,
                                 &line,
+                                tags,
                                 &format!("{} from pipe stream", i),
                             )
                             .await
                             {
-                                false => err_count += 1,
-                                _ => (),
+                                err_count += 1
                             }
                         }
                     }
@@ -2257,10 +5518,18 @@ pub(crate) async fn send_channel_messages(
             continue;
         }
 
-        match send_channel_message(client, &channel_id, &relay_url, &fnote, &format!("{}", i)).await
+        if !send_channel_message(
+            client,
+            outbox,
+            &channel_id,
+            &relay_url,
+            &fnote,
+            tags,
+            &format!("{}", i),
+        )
+        .await
         {
-            false => err_count += 1,
-            _ => (),
+            err_count += 1
         }
         i += 1;
     }
@@ -2273,24 +5542,28 @@ pub(crate) async fn send_channel_messages(
 
 /// Handle the --send-channel-message CLI argument
 /// Publish messages to one channel.
-pub(crate) async fn cli_send_channel_message(client: &Client, ap: &mut Args) -> Result<(), Error> {
+pub(crate) async fn cli_send_channel_message(
+    client: &Client,
+    ap: &mut Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
     let num = ap.send_channel_message.len();
     if num < 2 {
         return Err(Error::MissingCliParameter);
     }
+    let tags = cli_tags(ap)?;
     // todo: check if hash is valid, doable? check documentation
     match PublicKey::from_str(&ap.send_channel_message[0]) {
         Ok(channel_id) => {
             let notes = &ap.send_channel_message[1..];
-            let relay: Url;
-            if !ap.relay.is_empty() {
+            let relay: Url = if !ap.relay.is_empty() {
                 // todo: pass the vector of relays, not just one
-                relay = ap.relay[0].clone();
+                ap.relay[0].clone()
             } else {
-                relay = ap.creds.relays[0].clone().url;
-            }
+                ap.creds.relays[0].clone().url
+            };
             // todo: using empty relay-vector, should it be set?
-            send_channel_messages(client, notes, channel_id, relay).await
+            send_channel_messages(client, outbox, notes, channel_id, relay, &tags).await
         }
         Err(ref e) => {
             error!(
@@ -2382,7 +5655,8 @@ pub(crate) fn get_contact_alias_by_keystr(ap: &Args, pkeystr: &str) -> Option<St
 
 /// Handle the --add-contact CLI argument, write contacts from CLI args into creds data structure
 pub(crate) async fn cli_add_contact(client: &Client, ap: &mut Args) -> Result<(), Error> {
-    let mut err_count = 0usize;
+    // the first error is reported, invalid contacts are skipped
+    let mut err: Option<Error> = None;
     let anum = ap.alias.len();
     let knum = ap.key.len();
     let rnum = ap.relay.len();
@@ -2397,13 +5671,13 @@ pub(crate) async fn cli_add_contact(client: &Client, ap: &mut Args) -> Result<()
     while i < anum {
         if ap.alias[i].trim().is_empty() {
             error!("Invalid user alias. Cannot be empty. Skipping this contact.");
-            err_count += 1;
+            err.get_or_insert(Error::MissingCliParameter);
             i += 1;
             continue;
         }
         if get_contact_by_alias(ap, ap.alias[i].trim()).is_some() {
             error!("Invalid user alias. Alias already exists. Alias must be unique. Skipping this contact.");
-            err_count += 1;
+            err.get_or_insert(Error::MissingCliParameter);
             i += 1;
             continue;
         }
@@ -2412,7 +5686,7 @@ pub(crate) async fn cli_add_contact(client: &Client, ap: &mut Args) -> Result<()
                 "Relay {:?} is not valid. Skipping this contact.",
                 ap.relay[i]
             );
-            err_count += 1;
+            err.get_or_insert(Error::MissingCliParameter);
             i += 1;
             continue;
         }
@@ -2430,17 +5704,16 @@ pub(crate) async fn cli_add_contact(client: &Client, ap: &mut Args) -> Result<()
             }
             Err(ref e) => {
                 error!("Error: Invalid key {:?}. Skipping this contact.", key);
-                err_count += 1;
+                err.get_or_insert(Error::InvalidKey);
                 i += 1;
                 continue;
             }
         }
         i += 1;
     }
-    if err_count != 0 {
-        Err(Error::SubscriptionFailed)
-    } else {
-        Ok(())
+    match err {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
 }
 
@@ -2457,6 +5730,118 @@ pub(crate) async fn cli_remove_contact(client: &Client, ap: &mut Args) -> Result
     Ok(())
 }
 
+/// Merge contacts of a published contact list into the local contacts.
+/// Local aliases and relays win, missing ones are taken from the published
+/// contacts if the alias is still unique. Contacts in the synced list were
+/// removed on one side since the last sync, they are not added again but
+/// removed. Returns the contacts that were added.
+fn merge_contacts(
+    local: &mut Vec<Contact>,
+    published: Vec<Contact>,
+    synced: &[PublicKey],
+) -> Vec<Contact> {
+    // removed by another client
+    local.retain(|c| {
+        let removed = synced.contains(&c.public_key)
+            && !published.iter().any(|p| p.public_key == c.public_key);
+        if removed {
+            info!(
+                "Contact {:?} was removed from the published contact list.",
+                c.public_key
+            );
+        }
+        !removed
+    });
+    let mut added = Vec::new();
+    for mut contact in published {
+        let taken = contact
+            .alias
+            .as_ref()
+            .is_some_and(|a| local.iter().any(|c| c.alias.as_ref() == Some(a)));
+        if taken {
+            contact.alias = None;
+        }
+        match local
+            .iter_mut()
+            .find(|c| c.public_key == contact.public_key)
+        {
+            Some(existing) => {
+                if existing.alias.is_none() {
+                    existing.alias = contact.alias;
+                }
+                if existing.relay_url.is_none() {
+                    existing.relay_url = contact.relay_url;
+                }
+            }
+            // removed with --remove-contact
+            None if synced.contains(&contact.public_key) => {
+                debug!("Contact {:?} was removed locally.", contact.public_key);
+            }
+            None => {
+                local.push(contact.clone());
+                added.push(contact);
+            }
+        }
+    }
+    added
+}
+
+/// Handle the --sync-contacts CLI argument
+/// Fetch our published contact list and merge it into the local contacts.
+pub(crate) async fn cli_sync_contacts(client: &Client, ap: &mut Args) -> Result<(), Error> {
+    // without a connected relay the published contacts would look empty
+    let mut connected = false;
+    for relay in client.relays().await.values() {
+        connected |= relay.is_connected().await;
+    }
+    if !connected {
+        error!("Error: no relay is connected, cannot fetch contact list.");
+        return Err(Error::CannotConnectToRelays);
+    }
+    let timeout = Some(Duration::from_secs(ap.fetch_timeout));
+    let published = match client.get_contact_list(timeout).await {
+        Ok(contacts) => contacts,
+        Err(ref e) => {
+            error!(
+                "Error: fetching contact list failed. Reported error {:?}.",
+                e
+            );
+            return Err(Error::FetchFailed);
+        }
+    };
+    debug!("Fetched {} published contacts.", published.len());
+    let keys = published.iter().map(|c| c.public_key).collect();
+    let synced = std::mem::replace(&mut ap.creds.synced_contacts, keys);
+    for c in merge_contacts(&mut ap.creds.contacts, published, &synced) {
+        print_json(&json!(c), ap.output, 0, "");
+    }
+    Ok(())
+}
+
+/// Handle the --publish-contacts CLI argument
+/// Publish the local contacts as contact list (NIP-02).
+pub(crate) async fn cli_publish_contacts(
+    client: &Client,
+    ap: &mut Args,
+    outbox: &Outbox,
+) -> Result<(), Error> {
+    let builder = EventBuilder::contact_list(ap.creds.contacts.clone());
+    match send_builder_via_outbox(client, outbox, builder).await {
+        Ok(ref event_id) => {
+            debug!("Contact list published. event_id {:?}", event_id);
+            ap.creds.synced_contacts = ap.creds.contacts.iter().map(|c| c.public_key).collect();
+            Ok(())
+        }
+        Err(ref e) => {
+            error!(
+                "Error: publishing contact list failed. Reported error {:?}.",
+                e
+            );
+            Err(Error::PublishFailed)
+        }
+    }
+}
+
 /// Convert npub1... Bech32 key or Hex key or contact alias into a PublicKey
 /// Returns Error if neither valid Bech32, nor Hex key, nor contact alias.
 pub(crate) fn cstr_to_pubkey(ap: &Args, s: &str) -> Result<PublicKey, Error> {
@@ -2476,7 +5861,7 @@ pub(crate) fn str_to_pubkey(s: &str) -> Result<PublicKey, Error> {
                 s,
                 pkey.to_bech32().unwrap() // public_key
             );
-            return Ok(pkey);
+            Ok(pkey)
         }
         Err(ref e) => match PublicKey::from_str(s) {
             Ok(pkey) => {
@@ -2485,16 +5870,91 @@ pub(crate) fn str_to_pubkey(s: &str) -> Result<PublicKey, Error> {
                     s,
                     pkey.to_bech32().unwrap()
                 );
-                return Ok(pkey);
+                Ok(pkey)
+            }
+            Err(ref e) => {
+                error!("Error: Invalid key {:?}. Reported error: {:?}.", s, e);
+                Err(Error::InvalidKey)
             }
+        },
+    }
+}
+
+/// Convert Hex event id, note1... or nevent1... Bech32 string into an EventId
+/// Returns Error if neither valid Hex, note, nor nevent.
+pub(crate) fn str_to_event_id(s: &str) -> Result<EventId, Error> {
+    str_to_nevent(s).map(|nevent| nevent.event_id)
+}
+
+/// Convert Hex event id, note1... or nevent1... Bech32 string into an event
+/// pointer. The relay hints and the author of an nevent are kept.
+/// Returns Error if neither valid Hex, note, nor nevent.
+pub(crate) fn str_to_nevent(s: &str) -> Result<Nip19Event, Error> {
+    match EventId::parse(s) {
+        Ok(id) => Ok(Nip19Event::new(id, Vec::<String>::new())),
+        Err(_) => match Nip19Event::from_bech32(s) {
+            Ok(nevent) => Ok(nevent),
             Err(ref e) => {
-                error!("Error: Invalid key {:?}. Reported error: {:?}.", s, e);
-                return Err(Error::InvalidKey);
+                error!("Error: Invalid event id {:?}. Reported error: {:?}.", s, e);
+                Err(Error::InvalidEventId)
             }
         },
     }
 }
 
+/// Convert one --tag string into a Tag.
+/// Accepts 'letter:value', e.g. 't:nostr', 'p:alias', 'e:note1...', or a raw JSON array.
+/// For 'p' tags contact aliases are resolved.
+pub(crate) fn str_to_tag(ap: &Args, s: &str) -> Result<Tag, Error> {
+    let s = s.trim();
+    if s.starts_with('[') {
+        let values: Vec<String> = match serde_json::from_str(s) {
+            Ok(v) => v,
+            Err(ref e) => {
+                error!("Error: Invalid JSON tag {:?}. Reported error: {:?}.", s, e);
+                return Err(Error::InvalidTag);
+            }
+        };
+        return Tag::parse(&values).map_err(|e| {
+            error!("Error: Invalid tag {:?}. Reported error: {:?}.", s, e);
+            Error::InvalidTag
+        });
+    }
+    let (kind, value) = match s.split_once(':') {
+        Some((k, v)) if !k.trim().is_empty() && !v.trim().is_empty() => (k.trim(), v.trim()),
+        _ => {
+            error!(
+                "Error: Invalid tag {:?}. Use 'letter:value' like 't:nostr' or a JSON array.",
+                s
+            );
+            return Err(Error::InvalidTag);
+        }
+    };
+    match kind {
+        "p" => Ok(Tag::public_key(cstr_to_pubkey(ap, value)?)),
+        "e" => {
+            let nevent = str_to_nevent(value)?;
+            Ok(Tag::from_standardized_without_cell(TagStandard::Event {
+                event_id: nevent.event_id,
+                relay_url: nevent.relays.first().cloned().map(UncheckedUrl::from),
+                marker: None,
+                public_key: nevent.author,
+            }))
+        }
+        "t" => Ok(Tag::hashtag(value)),
+        _ => Tag::parse(&[kind, value]).map_err(|e| {
+            error!("Error: Invalid tag {:?}. Reported error: {:?}.", s, e);
+            Error::InvalidTag
+        }),
+    }
+}
+
+/// Convert all --tag strings into Tags.
+/// Returns Error if any of them is invalid, so nothing gets sent with wrong tags.
+pub(crate) fn cli_tags(ap: &Args) -> Result<Vec<Tag>, Error> {
+    ap.tag.iter().map(|s| str_to_tag(ap, s)).collect()
+}
+
 /// Convert npub1... Bech32 key or Hex key into a npub+hex pair as Vector.
 /// s ... input, npub ... output, hex ... output.
 /// Returns Error if neither valid Bech32 nor Hex key.
@@ -2509,7 +5969,7 @@ pub(crate) fn str_to_pubkeys(s: &str) -> Result<(String, String), Error> {
             let npub = s.to_owned();
             // todo: zzz not sure about this
             let hex = pkey.to_string(); // pkey.to_bech32().unwrap();
-            return Ok((npub, hex));
+            Ok((npub, hex))
         }
         Err(ref e) => match PublicKey::from_str(s) {
             Ok(pkey) => {
@@ -2520,11 +5980,11 @@ pub(crate) fn str_to_pubkeys(s: &str) -> Result<(String, String), Error> {
                 );
                 let npub = pkey.to_bech32().unwrap();
                 let hex = s.to_owned();
-                return Ok((npub, hex));
+                Ok((npub, hex))
             }
             Err(ref e) => {
                 error!("Error: Invalid key {:?}. Reported error: {:?}.", s, e);
-                return Err(Error::InvalidKey);
+                Err(Error::InvalidKey)
             }
         },
     }
@@ -2743,6 +6203,76 @@ pub(crate) async fn cli_unsubscribe_author(client: &Client, ap: &mut Args) -> Re
     }
 }
 
+/// Convert a NIP-01 filter given as JSON string to a Filter
+pub(crate) fn str_to_filter(s: &str) -> Result<Filter, Error> {
+    match Filter::from_json(s.trim()) {
+        Ok(filter) => Ok(filter),
+        Err(ref e) => {
+            error!("Error: Invalid filter {:?}. Reported error {:?}.", s, e);
+            Err(Error::InvalidFilter)
+        }
+    }
+}
+
+/// Handle the --filter CLI argument, return the filters of this run
+pub(crate) fn cli_filter(ap: &Args) -> Result<Vec<Filter>, Error> {
+    ap.filter.iter().map(|s| str_to_filter(s)).collect()
+}
+
+/// Handle the --subscribe-filter CLI argument, moving named filters from CLI args into creds data structure
+pub(crate) fn cli_subscribe_filter(ap: &mut Args) -> Result<(), Error> {
+    let mut err_count = 0usize;
+    for arg in ap.subscribe_filter.clone() {
+        let (name, json) = match arg.split_once('=') {
+            Some((name, json)) if !name.trim().is_empty() => (name.trim(), json),
+            _ => {
+                error!(
+                    "Error: Missing name in {:?}. Use the form 'NAME=JSON'. Not added to subscriptions.",
+                    arg
+                );
+                err_count += 1;
+                continue;
+            }
+        };
+        match str_to_filter(json) {
+            Ok(filter) => {
+                ap.creds.subscribed_filters.retain(|f| f.name != name);
+                ap.creds.subscribed_filters.push(NamedFilter {
+                    name: name.to_owned(),
+                    filter,
+                });
+                debug!("Valid filter {:?} added to subscriptions.", name);
+            }
+            Err(_) => err_count += 1,
+        }
+    }
+    if err_count != 0 {
+        Err(Error::SubscriptionFailed)
+    } else {
+        Ok(())
+    }
+}
+
+/// Handle the --unsubscribe-filter CLI argument, remove named filters from creds data structure
+pub(crate) fn cli_unsubscribe_filter(ap: &mut Args) -> Result<(), Error> {
+    let mut err_count = 0usize;
+    for name in ap.unsubscribe_filter.clone() {
+        let num = ap.creds.subscribed_filters.len();
+        ap.creds
+            .subscribed_filters
+            .retain(|f| f.name != name.trim());
+        if num == ap.creds.subscribed_filters.len() {
+            error!("Error: No filter named {:?}. Nothing removed.", name);
+            err_count += 1;
+        }
+    }
+    if err_count != 0 {
+        Err(Error::UnsubscribeFailed)
+    } else {
+        Ok(())
+    }
+}
+
 /// Handle the --subscribe-channel CLI argument, moving pkeys from CLI args into creds data structure
 pub(crate) async fn cli_subscribe_channel(client: &mut Client, ap: &mut Args) -> Result<(), Error> {
     let mut err_count = 0usize;
@@ -2831,9 +6361,7 @@ pub(crate) fn print_json(jsonv: &Value, output: Output, depth: u32, separator: &
                 print!("{}", jsonv);
             } else if jsonv.is_null() {
                 print!(""); // print nothing
-            } else if jsonv.is_string() {
-                print!("{}", jsonv);
-            } else if jsonv.is_number() {
+            } else if jsonv.is_string() || jsonv.is_number() {
                 print!("{}", jsonv);
             } else if jsonv.is_array() {
                 print!("[ ");
@@ -2851,7 +6379,7 @@ pub(crate) fn print_json(jsonv: &Value, output: Output, depth: u32, separator: &
                 print!(" ]");
             } else {
                 debug!("not implemented type in print_json()");
-                print!("{}", jsonv.to_string(),);
+                print!("{}", jsonv,);
             }
             if depth == 0 {
                 println!();
@@ -2860,11 +6388,20 @@ pub(crate) fn print_json(jsonv: &Value, output: Output, depth: u32, separator: &
         Output::JsonSpec => (),
         _ => {
             // This can panic if output is piped and pipe is broken by receiving process
-            println!("{}", jsonv.to_string(),);
+            println!("{}", jsonv,);
         }
     }
 }
 
+/// Print a JSON string that adheres to the Nostr Specification, e.g. a
+/// raw event or relay message. Only prints for output json-spec.
+pub(crate) fn print_json_spec(json: &str, output: Output) {
+    if output.is_json_spec() {
+        // This can panic if output is piped and pipe is broken by receiving process
+        println!("{}", json);
+    }
+}
+
 /// Handle the --whoami CLI argument
 pub(crate) fn cli_whoami(ap: &Args) -> Result<(), Error> {
     print_json(
@@ -2880,16 +6417,32 @@ pub(crate) fn cli_whoami(ap: &Args) -> Result<(), Error> {
 }
 
 /// We need your code contributions! Please add features and make PRs! :pray: :clap:
+/// Program entry point, the outcome of run() becomes the exit code.
+/// See Error::exit_code() for the meaning of the exit codes.
 #[tokio::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(ref e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Perform all the actions requested on the command line.
+/// Failing actions are logged and the remaining actions are still
+/// performed, the first failure is returned.
+async fn run() -> Result<(), Error> {
     let mut ap = Args::parse();
     let mut is_connected = false; // is this app connected to relays
+    let mut result: Result<(), Error> = Ok(()); // first failure, if any
 
     eprintln!("If you know Rust a bit, if you are interested in Nostr, ");
     eprintln!("then please consider making a code contribution. ");
     eprintln!("At the very least give it a star on Github. ");
     eprintln!("Star and make PRs at: https://github.com/8go/nostr-commander-rs ");
-    eprintln!("");
+    eprintln!();
 
     // handle log level and debug options
     let env_org_rust_log = env::var("RUST_LOG").unwrap_or_default().to_uppercase();
@@ -2996,6 +6549,10 @@ async fn main() -> Result<(), Error> {
     match ap.version {
         None => (),                        // do nothing
         Some(None) => crate::version(&ap), // print version
+        Some(Some(Version::Check)) if ap.proxy_all.is_some() => {
+            error!("Not checking crates.io, --proxy-all does not allow direct connections.");
+            result = result.and(Err(Error::ProxyFailed));
+        }
         Some(Some(Version::Check)) => crate::version_check(&ap),
     }
     if ap.contribute {
@@ -3014,9 +6571,39 @@ async fn main() -> Result<(), Error> {
         return Ok(());
     };
     if ap.readme {
+        if ap.proxy_all.is_some() {
+            error!("Not downloading README.md, --proxy-all does not allow direct connections.");
+            return Err(Error::ProxyFailed);
+        }
         crate::readme().await;
         return Ok(());
     };
+    if ap.fetch_dms {
+        ap.fetch = true;
+    }
+    if (ap.reply_to.is_some() || ap.quote.is_some())
+        && ap.publish.is_empty()
+        && ap.publish_pow.is_empty()
+    {
+        error!("--reply-to and --quote can only be used with --publish or --publish-pow.");
+        return Err(Error::UnsupportedCliParameter(
+            "--reply-to and --quote require --publish or --publish-pow",
+        ));
+    }
+    if cfg!(not(target_os = "linux")) && ap.proxy_user.is_some() {
+        // see Socks5Bridges::get()
+        error!("--proxy-user is only supported on Linux.");
+        return Err(Error::UnsupportedCliParameter(
+            "--proxy-user is only supported on Linux",
+        ));
+    }
+    if ap.delete_user.is_some() && ap.nsec.is_some() {
+        // the --nsec user would be deleted remotely, but the file of another user wiped
+        error!("--delete-user cannot be used together with --nsec. Nothing was deleted.");
+        return Err(Error::UnsupportedCliParameter(
+            "--delete-user cannot be combined with --nsec",
+        ));
+    }
 
     if ap.create_user {
         match crate::cli_create_user(&mut ap) {
@@ -3036,6 +6623,8 @@ async fn main() -> Result<(), Error> {
             Err(ref e) => {
                 if ap.nsec.is_some() {
                     debug!("User id will be taken from --nsec argument.");
+                } else if ap.delete_user == Some(None) {
+                    debug!("Credentials file cannot be read, but it will be deleted anyway.");
                 } else {
                     error!("Credentials file does not exists or cannot be read. Try creating a user first with --create-user. Check your arguments and try again. Worst case if file is corrupted or lost, consider doing a '--delete-user' to clean up, then perform a new '--create-user'. {:?}.", e);
                     return Err(Error::ReadingCredentialsFailed);
@@ -3046,12 +6635,26 @@ async fn main() -> Result<(), Error> {
     if ap.nsec.is_some() {
         debug!("We use private and public key from --nsec argument.");
         // parses from bech32 as well as from hex
-        let my_keys = Keys::parse(&ap.nsec.clone().unwrap())?;
+        let my_keys = Keys::parse(ap.nsec.clone().unwrap())?;
         ap.creds.public_key_bech32 = my_keys.public_key().to_bech32().unwrap();
         ap.creds.secret_key_bech32 = my_keys.secret_key().to_bech32().unwrap();
     }
     // credentials are filled now
 
+    // local delete does not need keys or relays, it also works on corrupted files
+    if ap.delete_user == Some(None) {
+        match crate::cli_delete_user(None, &ap).await {
+            Ok(()) => {
+                info!("delete_user successful.");
+                return Ok(());
+            }
+            Err(ref e) => {
+                error!("delete_user failed. Reported error is: {:?}", e);
+                return Err(Error::DeleteUserFailed);
+            }
+        }
+    }
+
     debug!("Welcome to nostr-commander-rs");
 
     let my_keys = Keys::parse(&ap.creds.secret_key_bech32)?;
@@ -3088,11 +6691,12 @@ async fn main() -> Result<(), Error> {
             Ok(()) => {
                 info!("Converting keys from npub to hex successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!(
                     "Converting keys from npub to hex failed. Reported error is: {:?}",
                     e
                 );
+                result = result.and(Err(e));
             }
         }
     }
@@ -3102,26 +6706,62 @@ async fn main() -> Result<(), Error> {
             Ok(()) => {
                 info!("Converting keys from hex to npub successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!(
                     "Converting keys from hex to npub failed. Reported error is: {:?}",
                     e
                 );
+                result = result.and(Err(e));
             }
         }
     }
     // Create new client
-    let mut client = Client::new(&my_keys);
+    let builder = if ap.store {
+        // keep the events in memory so that sent and received events can be stored
+        let database = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        Client::builder().signer(&my_keys).database(database)
+    } else {
+        Client::builder().signer(&my_keys)
+    };
+    let mut opts = Options::new();
+    if let Some(wait_ok) = ap.wait_ok {
+        // connect and send wait for the relays, up to the timeout
+        let timeout = Duration::from_secs(wait_ok.unwrap_or(WAIT_OK_TIMEOUT_DEFAULT));
+        opts = opts
+            .connection_timeout(Some(timeout))
+            .send_timeout(Some(timeout));
+    }
+    let mut client = builder.opts(opts).build();
+    let store: Option<Mutex<EventStore>> = if ap.store {
+        match EventStore::open(&get_event_store_path(&ap)) {
+            Ok(store) => Some(Mutex::new(store)),
+            Err(ref e) => {
+                error!(
+                    "Opening event store {:?} failed. Events will not be stored. Reported error is: {:?}",
+                    get_event_store_path(&ap),
+                    e
+                );
+                result = result.and(Err(Error::StorageFailure));
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     match add_relays_from_creds(&mut client, &mut ap).await {
         Ok(()) => {
             info!("Adding relays from credentials to client successful.");
         }
-        Err(ref e) => {
+        Err(e) => {
             error!(
                 "Adding relays from credentials to client failed. Reported error is: {:?}",
                 e
             );
+            result = result.and(Err(e));
         }
     }
     // todo clean up code to separate better local action from client/remote action
@@ -3131,8 +6771,9 @@ async fn main() -> Result<(), Error> {
             Ok(()) => {
                 info!("add_relay successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!("add_relay failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
@@ -3141,24 +6782,48 @@ async fn main() -> Result<(), Error> {
             Ok(()) => {
                 info!("remove_relay successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!("remove_relay failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
     ap.creds.relays.dedup_by(|a, b| a.url == b.url);
+    if ap.check_relays {
+        match crate::cli_check_relays(&mut ap).await {
+            Ok(()) => {
+                info!("check_relays successful.");
+            }
+            Err(e) => {
+                error!("check_relays failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
 
     trace!("checking to see if it is necessary to call connect.");
     // todo: further optimize: --unsubscribe-... could remove subscriptions and make subscriptions empty,
     // but this is not yet checked.
     if ap.listen
-        // || !ap.publish_pow.is_empty() // publish_pow_text_note discontinued since nostr-sdk v0.21.
+        || ap.fetch
+        || ap.change_metadata
+        || ap.show_metadata == Some(Some(ShowMetadata::Remote))
+        || ap.delete_user == Some(Some(DeleteUser::Remote))
+        || !ap.publish_pow.is_empty()
+        || !ap.react.is_empty()
+        || !ap.repost.is_empty()
+        || ap.dm_history.is_some()
         || !ap.publish.is_empty()
         || !ap.dm.is_empty()
         || !ap.send_channel_message.is_empty()
         || !ap.subscribe_pubkey.is_empty()
         || !ap.subscribe_author.is_empty()
         || !ap.subscribe_channel.is_empty()
+        || ap.flush_outbox
+        || ap.publish_relay_list
+        || ap.import_relay_list.is_some()
+        || ap.sync_contacts
+        || ap.publish_contacts
     {
         // design decision: avoid connect_...()  call if no relay action is needed and everything can be done locally.
         // design decision: avoid connect...() if no client is needed.
@@ -3168,31 +6833,116 @@ async fn main() -> Result<(), Error> {
         client.connect().await;
         info!("connect successful.");
         if client.relays().await.is_empty() {
-            error!("Client has no relay. Certain operations will fail. Consider using --add-relay argument.")
+            error!("Client has no relay. Certain operations will fail. Consider using --add-relay argument.");
+            result = result.and(Err(Error::CannotConnectToRelays));
         }
         is_connected = true;
     }
 
-    if ap.create_user {
-        // let metadata = Metadata::new()
-        //     .name("username")
-        //     .display_name("My Username")
-        //     .about("Description")
-        //     .picture(Url::from_str("https://example.com/avatar.png")?)
-        //     .nip05("username@example.com");
+    // events left over from earlier runs are retried whenever we are connected
+    let outbox = Outbox::new(get_outbox_path(&ap))
+        .with_routing(&ap)
+        .with_output(ap.output);
+    if is_connected {
+        match crate::flush_outbox(&client, &outbox, ap.flush_outbox).await {
+            Ok(()) => {
+                if ap.flush_outbox {
+                    info!("flush_outbox successful.")
+                }
+            }
+            Err(e) => {
+                error!("flush_outbox failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+
+    if ap.show_outbox {
+        match crate::cli_show_outbox(&ap) {
+            Ok(()) => info!("show_outbox successful."),
+            Err(e) => {
+                error!("show_outbox failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
 
-        // Update profile metadata
-        // client.update_profile() was removed from nostr-sdk API
+    if ap.delete_user == Some(Some(DeleteUser::Remote)) {
+        match crate::cli_delete_user(Some((&client, &outbox)), &ap).await {
+            Ok(()) => {
+                info!("delete_user successful.");
+                // user is gone, nothing else must be done, nothing must be saved
+                if ap.wait_ok.is_some() {
+                    return crate::cli_wait_ok(&ap, &outbox);
+                }
+                return Ok(());
+            }
+            Err(ref e) => {
+                error!("delete_user failed. Reported error is: {:?}", e);
+                return Err(Error::DeleteUserFailed);
+            }
+        }
+    }
+    if ap.create_user && is_connected {
+        // Publish profile metadata of the newly created user
+        match crate::publish_metadata(&client, &ap, &outbox).await {
+            Ok(()) => {
+                info!("publishing metadata of new user successful.");
+            }
+            Err(e) => {
+                error!(
+                    "publishing metadata of new user failed. Reported error is: {:?}",
+                    e
+                );
+                result = result.and(Err(e));
+            }
+        }
+    }
+    if ap.change_metadata {
+        match crate::cli_change_metadata(&client, &mut ap, &outbox).await {
+            Ok(()) => {
+                info!("change_metadata successful.");
+            }
+            Err(e) => {
+                error!("change_metadata failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    if ap.show_metadata.is_some() {
+        match crate::cli_show_metadata(&client, &ap).await {
+            Ok(()) => {
+                info!("show_metadata successful.");
+            }
+            Err(e) => {
+                error!("show_metadata failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
     }
 
-    // Set contacts, first in local file, second in client
+    // Set contacts, first merge the published ones, then apply local changes, last publish
+    let mut contacts_synced = false;
+    if ap.sync_contacts || ap.publish_contacts {
+        match crate::cli_sync_contacts(&client, &mut ap).await {
+            Ok(()) => {
+                info!("sync_contacts successful.");
+                contacts_synced = true;
+            }
+            Err(e) => {
+                error!("sync_contacts failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
     if ap.add_contact {
         match crate::cli_add_contact(&client, &mut ap).await {
             Ok(()) => {
                 info!("add_contact successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!("add_contact failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
@@ -3201,24 +6951,32 @@ async fn main() -> Result<(), Error> {
             Ok(()) => {
                 info!("remove_contact successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!("remove_contact failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
-    ap.creds.contacts.dedup_by(|a, b| a.alias == b.alias);
-    if is_connected {
-        trace!("setting contact list on server.");
-        match client.set_contact_list(ap.creds.contacts.clone()).await {
-            Ok(ref event_id) => {
-                info!("set_contact_list successful. event_id {:?}", event_id);
-            }
-            Err(ref e) => {
-                error!("set_contact_list failed. Reported error is: {:?}", e);
+    ap.creds
+        .contacts
+        .dedup_by(|a, b| a.alias.is_some() && a.alias == b.alias);
+    if ap.publish_contacts {
+        if contacts_synced {
+            match crate::cli_publish_contacts(&client, &mut ap, &outbox).await {
+                Ok(()) => {
+                    info!("publish_contacts successful.");
+                }
+                Err(e) => {
+                    error!("publish_contacts failed. Reported error is: {:?}", e);
+                    result = result.and(Err(e));
+                }
             }
+        } else {
+            // publishing would replace contacts added by other clients
+            error!(
+                "Not publishing contacts, because the published contact list could not be fetched."
+            );
         }
-    } else {
-        trace!("not setting contact list on server, because we are not connected.");
     }
     if ap.show_contacts {
         if ap.output.is_text() {
@@ -3231,114 +6989,338 @@ async fn main() -> Result<(), Error> {
     }
     // ap.creds.save(get_credentials_actual_path(&ap))?; // do it later
 
+    // Relay lists, importing first so that a published list includes the imported relays
+    if ap.import_relay_list.is_some() {
+        match crate::cli_import_relay_list(&mut client, &mut ap).await {
+            Ok(()) => {
+                info!("import_relay_list successful.");
+            }
+            Err(e) => {
+                error!("import_relay_list failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    if ap.publish_relay_list {
+        match crate::cli_publish_relay_list(&client, &ap, &outbox).await {
+            Ok(()) => {
+                info!("publish_relay_list successful.");
+            }
+            Err(e) => {
+                error!("publish_relay_list failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+
     trace!("checking if something needs to be published.");
     // Publish a text note
     if !ap.publish.is_empty() {
-        match crate::cli_publish(&client, &mut ap).await {
+        match crate::cli_publish(&client, &mut ap, &outbox).await {
             Ok(()) => {
                 info!("publish successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!("publish failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // Publish a POW text note, nonce is mined locally
+    if !ap.publish_pow.is_empty() {
+        match crate::cli_publish_pow(&client, &mut ap, &outbox).await {
+            Ok(()) => {
+                info!("publish_pow successful.");
+            }
+            Err(e) => {
+                error!("publish_pow failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // React to an event
+    if !ap.react.is_empty() {
+        match crate::cli_react(&client, &ap, &outbox).await {
+            Ok(()) => {
+                info!("react successful.");
+            }
+            Err(e) => {
+                error!("react failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // Repost events
+    if !ap.repost.is_empty() {
+        match crate::cli_repost(&client, &ap, &outbox).await {
+            Ok(()) => {
+                info!("repost successful.");
+            }
+            Err(e) => {
+                error!("repost failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
-    // publish_pow_text_note discontinued since nostr-sdk v0.21.
-    // // Publish a POW text note
-    // if !ap.publish_pow.is_empty() {
-    //     match crate::cli_publish_pow(&client, &mut ap).await {
-    //         Ok(()) => {
-    //             info!("publish_pow successful.");
-    //         }
-    //         Err(ref e) => {
-    //             error!("publish_pow failed. Reported error is: {:?}", e);
-    //         }
-    //     }
-    // }
     // Send DMs
     if !ap.dm.is_empty() {
-        match crate::cli_dm(&client, &mut ap).await {
+        match crate::cli_dm(&client, &mut ap, &outbox).await {
             Ok(()) => {
                 info!("dm successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!("dm failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // Print DM history
+    if ap.dm_history.is_some() {
+        match crate::cli_dm_history(&client, &ap).await {
+            Ok(()) => {
+                info!("dm_history successful.");
+            }
+            Err(e) => {
+                error!("dm_history failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
     // Send channel messages
     if !ap.send_channel_message.is_empty() {
-        match crate::cli_send_channel_message(&client, &mut ap).await {
+        match crate::cli_send_channel_message(&client, &mut ap, &outbox).await {
             Ok(()) => {
                 info!("send-channel-message successful.");
             }
-            Err(ref e) => {
+            Err(e) => {
                 error!("send-channel-message failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // Report which relays acknowledged the events sent above
+    if ap.wait_ok.is_some() {
+        match crate::cli_wait_ok(&ap, &outbox) {
+            Ok(()) => {
+                info!("wait_ok successful.");
+            }
+            Err(e) => {
+                error!("wait_ok failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
 
+    // filters of the subscriptions, used by --listen and --fetch
+    let mut subscription_filters: Vec<(Filter, String)> = Vec::new();
     // Subscribe keys
     if !ap.subscribe_pubkey.is_empty() {
         match crate::cli_subscribe_pubkey(&mut client, &mut ap).await {
             Ok(()) => {
                 debug!("subscribe_pubkey successful. Subscriptions synchronized with credentials file.");
             }
-            Err(ref e) => {
-                error!("subscribe_pubkey failed. Reported error is: {:?}", e);
+            Err(e) => {
+                error!("subscribe_pubkey failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    if !ap.creds.subscribed_pubkeys.is_empty() && (ap.listen || ap.fetch) {
+        let filter = Filter::new().pubkeys(ap.creds.subscribed_pubkeys.clone());
+        subscription_filters.push((filter, "keys".to_owned()));
+    }
+    // Subscribe authors
+    if !ap.subscribe_author.is_empty() {
+        match crate::cli_subscribe_author(&mut client, &mut ap).await {
+            Ok(()) => {
+                debug!("subscribe_author successful. Subscriptions synchronized with credentials file.");
+            }
+            Err(e) => {
+                error!("subscribe_author failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    if !ap.creds.subscribed_authors.is_empty() && (ap.listen || ap.fetch) {
+        let filter = Filter::new().authors(ap.creds.subscribed_authors.clone());
+        subscription_filters.push((filter, "authors".to_owned()));
+    }
+    // Subscribe channels
+    if !ap.subscribe_channel.is_empty() {
+        match crate::cli_subscribe_channel(&mut client, &mut ap).await {
+            Ok(()) => {
+                debug!("subscribe_channel successful. Subscriptions synchronized with credentials file.");
+            }
+            Err(e) => {
+                error!("subscribe_channel failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // Unsubscribe channels
+    if !ap.unsubscribe_channel.is_empty() {
+        match crate::cli_unsubscribe_channel(&client, &mut ap).await {
+            Ok(()) => {
+                debug!("unsubscribe_channel successful. Subscriptions synchronized with credentials file.");
+            }
+            Err(e) => {
+                error!("unsubscribe_channel failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    if !ap.creds.subscribed_channels.is_empty() && (ap.listen || ap.fetch) {
+        let filter = Filter::new().pubkeys(ap.creds.subscribed_channels.clone());
+        subscription_filters.push((filter, "channels".to_owned()));
+    }
+    // Subscribe filters
+    if !ap.subscribe_filter.is_empty() {
+        match crate::cli_subscribe_filter(&mut ap) {
+            Ok(()) => {
+                debug!("subscribe_filter successful. Subscriptions synchronized with credentials file.");
+            }
+            Err(e) => {
+                error!("subscribe_filter failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // Unsubscribe filters
+    if !ap.unsubscribe_filter.is_empty() {
+        match crate::cli_unsubscribe_filter(&mut ap) {
+            Ok(()) => {
+                debug!("unsubscribe_filter successful. Subscriptions synchronized with credentials file.");
+            }
+            Err(e) => {
+                error!("unsubscribe_filter failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    if ap.listen || ap.fetch {
+        for f in &ap.creds.subscribed_filters {
+            subscription_filters.push((f.filter.clone(), f.name.clone()));
+        }
+        match crate::cli_filter(&ap) {
+            Ok(filters) => {
+                for filter in filters {
+                    subscription_filters.push((filter, "filter".to_owned()));
+                }
+            }
+            Err(e) => {
+                error!("filter failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    // limits are applied once, the event store can then move 'since' forward
+    let mut subscription_filters: Vec<(Filter, String)> = subscription_filters
+        .into_iter()
+        .map(|(f, n)| (limit_filter(&ap, f), n))
+        .collect();
+    // Subscribe DMs addressed to us, both NIP-04 and NIP-17
+    if ap.listen || ap.fetch_dms {
+        match str_to_pubkey(&ap.creds.public_key_bech32) {
+            Ok(pk) => {
+                let filter = Filter::new()
+                    .pubkey(pk)
+                    .kinds([Kind::EncryptedDirectMessage, Kind::GiftWrap]);
+                let filter = widen_for_gift_wraps(limit_filter(&ap, filter));
+                subscription_filters.push((filter, "DMs".to_owned()));
+            }
+            Err(e) => {
+                error!("Cannot subscribe to DMs. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
-    if !ap.creds.subscribed_pubkeys.is_empty() && ap.listen {
-        let filter = Filter::new().pubkeys(ap.creds.subscribed_pubkeys.clone());
-        subscribe_to_filter(&client, &ap, filter, "keys").await;
-    }
-    // Subscribe authors
-    if !ap.subscribe_author.is_empty() {
-        match crate::cli_subscribe_author(&mut client, &mut ap).await {
-            Ok(()) => {
-                debug!("subscribe_author successful. Subscriptions synchronized with credentials file.");
+    // events already printed, relays can deliver them again
+    let mut printed: HashSet<EventId> = HashSet::new();
+    // subscriptions waiting for EOSE from relays, to record the synced range in the store
+    let mut unsynced: HashMap<SubscriptionId, (Filter, HashSet<Url>)> = HashMap::new();
+    let synced_until = Timestamp::now();
+    if ap.listen {
+        if let Some(ref store) = store {
+            let (filters, names): (Vec<Filter>, Vec<String>) =
+                subscription_filters.into_iter().unzip();
+            let (stored, filters) = store.lock().unwrap().catch_up(filters);
+            // with --fetch the stored events are printed by the fetch
+            if !ap.fetch {
+                for event in &stored {
+                    print_json_spec(&event.as_json(), ap.output);
+                    handle_event(&client, &ap, event, None, None).await;
+                }
             }
-            Err(ref e) => {
-                error!("subscribe_author failed. Reported error is: {:?}", e);
+            printed.extend(stored.iter().map(|e| e.id));
+            subscription_filters = filters.into_iter().zip(names).collect();
+        }
+        for (filter, filter_name) in &subscription_filters {
+            match subscribe_to_filter(&client, filter.clone(), filter_name).await {
+                // relays that did not get the subscription will not send their events
+                Ok(output) if output.failed.is_empty() => {
+                    unsynced.insert(output.val, (filter.clone(), output.success));
+                }
+                Ok(_) => (),
+                Err(e) => result = result.and(Err(e)),
             }
         }
     }
-    if !ap.creds.subscribed_authors.is_empty() && ap.listen {
-        let filter = Filter::new().authors(ap.creds.subscribed_authors.clone());
-        subscribe_to_filter(&client, &ap, filter, "authors").await;
+    if ap.nsec.is_none() || ap.create_user {
+        debug!("Creating or updating credentials file.");
+        if let Err(ref e) = ap.creds.save(get_credentials_actual_path(&ap)) {
+            error!(
+                "Error: writing credentials file failed. Reported error {:?}.",
+                e
+            );
+            return Err(Error::StorageFailure);
+        }
+    } else {
+        debug!("Not creating or not updating credentials file.")
     }
-    // Subscribe channels
-    if !ap.subscribe_channel.is_empty() {
-        match crate::cli_subscribe_channel(&mut client, &mut ap).await {
+
+    // Fetch stored events once
+    if ap.fetch {
+        let filters = subscription_filters.into_iter().map(|(f, _)| f).collect();
+        match crate::cli_fetch(&client, &ap, filters, store.as_ref()).await {
             Ok(()) => {
-                debug!("subscribe_channel successful. Subscriptions synchronized with credentials file.");
+                info!("fetch successful.");
             }
-            Err(ref e) => {
-                error!("subscribe_channel failed. Reported error is: {:?}", e);
+            Err(e) => {
+                error!("fetch failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
-    // Unsubscribe channels
-    if !ap.unsubscribe_channel.is_empty() {
-        match crate::cli_unsubscribe_channel(&mut client, &mut ap).await {
+    // Query the local event store
+    if !ap.query.is_empty() {
+        match crate::cli_query(&client, &ap).await {
             Ok(()) => {
-                debug!("unsubscribe_channel successful. Subscriptions synchronized with credentials file.");
+                info!("query successful.");
             }
-            Err(ref e) => {
-                error!("unsubscribe_channel failed. Reported error is: {:?}", e);
+            Err(e) => {
+                error!("query failed. Reported error is: {:?}", e);
+                result = result.and(Err(e));
             }
         }
     }
-    if !ap.creds.subscribed_channels.is_empty() && ap.listen {
-        let filter = Filter::new().pubkeys(ap.creds.subscribed_channels.clone());
-        subscribe_to_filter(&client, &ap, filter, "channels").await;
-    }
-    if ap.nsec.is_none() || ap.create_user {
-        debug!("Creating or updating credentials file.");
-        ap.creds.save(get_credentials_actual_path(&ap))?;
-    } else {
-        debug!("Not creating or not updating credentials file.")
+    // Store the events sent and received so far
+    if let Some(ref store) = store {
+        match client.database().query(vec![Filter::new()]).await {
+            Ok(events) => match store.lock().unwrap().add(&events) {
+                Ok(num) => debug!("Stored {} new events in event store.", num),
+                Err(ref e) => {
+                    error!("Storing events failed. Reported error is: {:?}", e);
+                    result = result.and(Err(Error::StorageFailure));
+                }
+            },
+            Err(ref e) => {
+                error!(
+                    "Reading events from database failed. Reported error is: {:?}",
+                    e
+                );
+                result = result.and(Err(Error::StorageFailure));
+            }
+        }
     }
 
     // notices will be published even if we do not go into handle_notification event loop
@@ -3347,7 +7329,12 @@ async fn main() -> Result<(), Error> {
     // || !ap.creds.subscribed_authors.is_empty()
     // || !ap.creds.subscribed_pubkeys.is_empty()
     {
-        let mut num = ap.publish.len() + ap.dm.len() + ap.send_channel_message.len(); // + ap.publish_pow.len() // publish_pow_text_note discontinued since nostr-sdk v0.21.
+        let mut num = ap.publish.len()
+            + ap.publish_pow.len()
+            + ap.dm.len()
+            + ap.send_channel_message.len()
+            + ap.react.len().min(1)
+            + ap.repost.len();
         if ap.dm.len() > 1 {
             num -= 1; //adjust num, 1st arg of dm is key not msg
         }
@@ -3365,6 +7352,9 @@ async fn main() -> Result<(), Error> {
                 num
             );
         }
+        let seen: Mutex<HashSet<EventId>> = Mutex::new(printed);
+        let storage_failed = AtomicBool::new(false);
+        let unsynced = Mutex::new(unsynced);
         // Handle notifications
         match client
             .handle_notifications(|notification| async {
@@ -3386,6 +7376,23 @@ async fn main() -> Result<(), Error> {
                     }
                     Message {relay_url, message } => {
                         // debug!("Message: {:?}", message);
+                        if let RelayMessage::Event { event, .. } = &message {
+                            // the same event is delivered by every relay that has it
+                            if !seen.lock().unwrap().insert(event.id) {
+                                trace!("Skipping duplicate event {:?} from {:?}.", event.id, relay_url);
+                                return Ok(false);
+                            }
+                            if let Some(ref store) = store {
+                                if let Err(ref e) = store.lock().unwrap().add(std::slice::from_ref(event.as_ref())) {
+                                    error!("Storing event failed. Reported error is: {:?}", e);
+                                    storage_failed.store(true, AtomicOrdering::Relaxed);
+                                }
+                            }
+                        }
+                        // OK messages of published events are printed by send_via_outbox()
+                        if let RelayMessage::Event { .. } = message {
+                            print_json_spec(&message.as_json(), ap.output);
+                        }
                         match message {
                             RelayMessage::Ok {event_id, status, message } => {
                                 // Notification: ReceivedMessage(Ok { event_id: 123, status: true, message: "" })
@@ -3413,90 +7420,24 @@ async fn main() -> Result<(), Error> {
                                 debug!("Message-Notice: {:?}", message);
                             }
                             RelayMessage::Event {event, subscription_id}=> {
-                                // kind: Base(ChannelMessage) and Base(TextNote) and Base(Reaction)
-                                let mut tags = "".to_owned();
-                                let first = true;
-                                for t in &event.tags {
-                                    match t.kind() {
-                                        TagKind::SingleLetter(p) => {
-                                            trace!("tag vector: {:?}", t.as_slice());
-                                            //match t.content() {
-                                            //    Some(c) => {
-                                            //        trace!("tag: {:?}", get_contact_alias_or_keystr_by_keystr(&ap, c));
-                                            //        match get_contact_alias_by_keystr(&ap, c) {
-                                            //            Some(a) => {
-                                            //                if !first { tags += ", "; };
-                                            //                tags += &a;
-                                            //                first = false;
-                                            //                },
-                                            //            _ => ()
-                                            //        }
-                                            //    }
-                                            //    None => ()
-                                            //}
-                                        },
-                                        // TagKind::SingleLetter(e) => info!("Single letter message received. Not implemented. {:?}",e),  // todo!(),
-                                        TagKind::Nonce => info!("Nonce message received. Not implemented."),  // todo!(),
-                                        TagKind::Delegation => info!("Delegation message received. Not implemented."),  // todo!(),
-                                        TagKind::ContentWarning => info!("ContentWarning message received. Not implemented."),  // todo!(),
-                                        TagKind::Custom(_) => info!("Custom message received. Not implemented."),  // todo!(),
-                                        _ => info!("Other message received. Not implemented."),  // todo!(),
-                                    }
-                                }
-                                trace!("Message-Event: content {:?}, kind {:?}, from pubkey {:?}, with tags {:?}", event.content, event.kind, get_contact_alias_or_keystr_by_key(&ap, event.pubkey), event.tags);
-                                let mut key_author = "key";
-                                if is_subscribed_author(&ap, &event.pubkey) {
-                                            key_author = "author";
-                                            tags = get_contact_alias_or_keystr_by_key(&ap, event.pubkey);
-                                        };
-                                match event.kind {
-                                    Kind::ContactList => {
-                                        debug!("Received Message-Event ContactList");
-                                    },
-                                    Kind::Reaction => {
-                                        debug!("Received Message-Event Reaction: content {:?}", event.content);
-                                    },
-                                    Kind::TextNote => {
-                                        info!("Subscription by {} ({}): content {:?}, kind {:?}, from pubkey {:?}", key_author, tags, event.content, event.kind, get_contact_alias_or_keystr_by_key(&ap, event.pubkey));
-                                        print_json(
-                                            &json!({
-                                                "event_type": "RelayMessage::Event",
-                                                "event_type_meaning": "Message was received because of subscription.",
-                                                "subscribed_by": key_author,
-                                                "author": get_contact_alias_or_keystr_by_key(&ap, event.pubkey),
-                                                "content": event.content,
-                                                "kind": event.kind, // writes integer like '1'
-                                                "kind_text": format!("{:?}",event.kind), // writes text like "Base(TextNote)"
-                                                "from_alias": get_contact_alias_or_keystr_by_key(&ap, event.pubkey),
-                                                "from_pubkey": event.pubkey,
-                                                "tags": tags
-                                            }) ,
-                                            ap.output,0,""
-                                        );
-                                    },
-                                    Kind::ChannelMessage => {
-                                        info!("Subscription by {} ({}): content {:?}, kind {:?}, from pubkey {:?}", key_author, tags, event.content, event.kind, get_contact_alias_or_keystr_by_key(&ap, event.pubkey));
-                                        print_json(
-                                            &json!({
-                                                "event_type": "RelayMessage::Event",
-                                                "event_type_meaning": "Message was received because of subscription.",
-                                                "subscribed_by": key_author,
-                                                "author": get_contact_alias_or_keystr_by_key(&ap, event.pubkey),
-                                                "content": event.content,
-                                                "kind": event.kind, // writes integer like '1'
-                                                "kind_text": format!("{:?}",event.kind), // writes text like "Base(TextNote)"
-                                                "from_alias": get_contact_alias_or_keystr_by_key(&ap, event.pubkey),
-                                                "from_pubkey": event.pubkey,
-                                                "tags": tags
-                                            }) ,
-                                            ap.output,0,""
-                                        );
-                                    },
-                                    _ => ()
-                                }
+                                handle_event(&client, &ap, &event, Some(&relay_url), Some(&subscription_id)).await;
                             },
                             RelayMessage::EndOfStoredEvents(subscription_id) =>  {
                                 debug!("Received Message-Event EndOfStoredEvents");
+                                // synced once every relay sent all its stored events
+                                let mut unsynced = unsynced.lock().unwrap();
+                                if let Some((filter, relays)) = unsynced.get_mut(&subscription_id) {
+                                    relays.remove(&relay_url);
+                                    if relays.is_empty() {
+                                        if let Some(ref store) = store {
+                                            if let Err(ref e) = store.lock().unwrap().mark_synced(filter, synced_until) {
+                                                error!("Recording synced events failed. Reported error is: {:?}", e);
+                                                storage_failed.store(true, AtomicOrdering::Relaxed);
+                                            }
+                                        }
+                                        unsynced.remove(&subscription_id);
+                                    }
+                                }
                             },
                             RelayMessage::Auth { challenge } =>  {
                                 debug!("Received Message-Event Auth");
@@ -3525,15 +7466,409 @@ async fn main() -> Result<(), Error> {
             }
             Err(ref e) => {
                 error!("handle_notifications failed. Reported error is: {:?}", e);
+                result = result.and(Err(Error::ListenFailed));
             }
         }
+        if storage_failed.load(AtomicOrdering::Relaxed) {
+            result = result.and(Err(Error::StorageFailure));
+        }
     }
 
+    if let Err(ref e) = client.shutdown().await {
+        debug!("Shutting down client failed. Reported error is: {:?}", e);
+    }
     debug!("Good bye");
+    result
+}
+
+/// Print a received event, used by --listen and --fetch.
+async fn handle_event(
+    client: &Client,
+    ap: &Args,
+    event: &nostr_sdk::Event,
+    relay_url: Option<&Url>,
+    subscription_id: Option<&SubscriptionId>,
+) {
+    trace!(
+        "Message-Event: content {:?}, kind {:?}, from pubkey {:?}, with tags {:?}",
+        event.content,
+        event.kind,
+        get_contact_alias_or_keystr_by_key(ap, event.pubkey),
+        event.tags
+    );
+    let mut tags = "".to_owned();
+    let mut key_author = "key";
+    if is_subscribed_author(ap, &event.pubkey) {
+        key_author = "author";
+        tags = get_contact_alias_or_keystr_by_key(ap, event.pubkey);
+    };
+    match event.kind {
+        Kind::EncryptedDirectMessage | Kind::GiftWrap => match decrypt_dm(client, event).await {
+            // the copy of a DM we sent, wrapped for ourselves in send_dm()
+            Ok(dm) if is_sent_dm(ap, &dm) => {
+                debug!("Own copy of DM sent to {:?} is not printed.", dm.recipient)
+            }
+            Ok(dm) => {
+                info!(
+                    "DM ({}) from {:?}: content {:?}",
+                    dm.protocol,
+                    get_contact_alias_or_keystr_by_key(ap, dm.sender),
+                    dm.content
+                );
+                let mut jsonv = dm_json(ap, &dm);
+                add_json_max(ap, &mut jsonv, event, relay_url, subscription_id);
+                print_json(&jsonv, ap.output, 0, "");
+            }
+            Err(ref e) => debug!(
+                "Received DM could not be decrypted. Reported error is: {:?}",
+                e
+            ),
+        },
+        _ => {
+            let summary = event_summary(ap, event);
+            info!(
+                "Subscription by {} ({}): content {:?}, kind {:?}, from pubkey {:?}, summary {:?}",
+                key_author,
+                tags,
+                event.content,
+                event.kind,
+                get_contact_alias_or_keystr_by_key(ap, event.pubkey),
+                summary
+            );
+            // in text mode mentions in the content are made readable
+            let content = if ap.output.is_text() {
+                expand_nostr_uris(ap, &event.content)
+            } else {
+                event.content.clone()
+            };
+            let mut jsonv = json!({
+                "event_type": "RelayMessage::Event",
+                "event_type_meaning": "Message was received because of subscription.",
+                "subscribed_by": key_author,
+                "author": get_contact_alias_or_keystr_by_key(ap, event.pubkey),
+                "content": content,
+                "kind": event.kind, // writes integer like '1'
+                "kind_text": format!("{:?}",event.kind), // writes text like "TextNote"
+                "summary": summary,
+                "from_alias": get_contact_alias_or_keystr_by_key(ap, event.pubkey),
+                "from_pubkey": event.pubkey,
+                "tags": tags
+            });
+            add_decoded_tags(ap, &mut jsonv, event);
+            add_json_max(ap, &mut jsonv, event, relay_url, subscription_id);
+            print_json(&jsonv, ap.output, 0, "");
+        }
+    }
+}
+
+/// Shorten a bech32 string for display, e.g. 'note1qqqqqqqqqqqq...wxyz'.
+fn short_bech32(s: &str) -> String {
+    if s.len() <= 24 {
+        s.to_owned()
+    } else {
+        format!("{}...{}", &s[..16], &s[s.len() - 4..])
+    }
+}
+
+/// Display a key: its contact alias, or its npub, shortened in text mode.
+fn key_display(ap: &Args, pk: &PublicKey) -> String {
+    match get_contact_alias_by_key(ap, *pk) {
+        Some(alias) => alias,
+        None => {
+            let npub = pk.to_bech32().unwrap_or_else(|_| pk.to_hex());
+            if ap.output.is_text() {
+                short_bech32(&npub)
+            } else {
+                npub
+            }
+        }
+    }
+}
+
+/// Expand NIP-27 'nostr:' URIs in the content. Keys become '@alias' or a
+/// short npub, events and addresses become short ids.
+pub(crate) fn expand_nostr_uris(ap: &Args, content: &str) -> String {
+    let re =
+        Regex::new(r"nostr:(?:npub1|nprofile1|note1|nevent1|naddr1)[02-9ac-hj-np-z]+").unwrap();
+    re.replace_all(content, |caps: &regex::Captures| {
+        let uri = &caps[0];
+        let bech32 = &uri["nostr:".len()..];
+        match Nip19::from_bech32(bech32) {
+            Ok(Nip19::Pubkey(pk)) => format!("@{}", key_display(ap, &pk)),
+            Ok(Nip19::Profile(profile)) => format!("@{}", key_display(ap, &profile.public_key)),
+            Ok(_) => short_bech32(bech32),
+            Err(_) => uri.to_owned(),
+        }
+    })
+    .into_owned()
+}
+
+/// Add the decoded tags of an event: mentioned people, referenced
+/// events, hashtags and content warning. Empty ones are left out.
+fn add_decoded_tags(ap: &Args, jsonv: &mut Value, event: &nostr_sdk::Event) {
+    let mentions: Vec<String> = event.public_keys().map(|pk| key_display(ap, pk)).collect();
+    let events: Vec<String> = event
+        .event_ids()
+        .map(|id| id.to_bech32().unwrap_or_else(|_| id.to_hex()))
+        .collect();
+    let hashtags: Vec<&String> = event.hashtags().collect();
+    if !mentions.is_empty() {
+        jsonv["mentions"] = json!(mentions);
+    }
+    if !events.is_empty() {
+        jsonv["referenced_events"] = json!(events);
+    }
+    if !hashtags.is_empty() {
+        jsonv["hashtags"] = json!(hashtags);
+    }
+    if let Some(t) = event
+        .tags
+        .iter()
+        .find(|t| t.kind() == TagKind::ContentWarning)
+    {
+        jsonv["content_warning"] = json!(t.content().unwrap_or_default());
+    }
+}
+
+/// For output json-max add the full signed event, where it came from,
+/// and the aliases of all keys referenced in 'p' tags.
+fn add_json_max(
+    ap: &Args,
+    jsonv: &mut Value,
+    event: &nostr_sdk::Event,
+    relay_url: Option<&Url>,
+    subscription_id: Option<&SubscriptionId>,
+) {
+    if ap.output != Output::JsonMax {
+        return;
+    }
+    let p_tags: Vec<Value> = event
+        .public_keys()
+        .map(|pk| {
+            json!({
+                "pubkey": pk,
+                "alias": get_contact_alias_by_key(ap, *pk),
+            })
+        })
+        .collect();
+    jsonv["event"] = json!(event);
+    jsonv["relay_url"] = json!(relay_url);
+    jsonv["subscription_id"] = json!(subscription_id);
+    jsonv["p_tags"] = json!(p_tags);
+}
+
+/// Describe an event in a short human readable sentence.
+/// Returns None for kinds whose content speaks for itself, e.g. text notes.
+pub(crate) fn event_summary(ap: &Args, event: &nostr_sdk::Event) -> Option<String> {
+    let target_event = || {
+        event
+            .event_ids()
+            .last()
+            .map(|id| id.to_bech32().unwrap_or_else(|_| id.to_hex()))
+            .unwrap_or_else(|| "unknown event".to_owned())
+    };
+    let first_key = || {
+        event
+            .public_keys()
+            .next()
+            .map(|pk| get_contact_alias_or_keystr_by_key(ap, *pk))
+            .unwrap_or_else(|| "unknown".to_owned())
+    };
+    let tag_content = |kind: TagKind| {
+        event
+            .tags
+            .iter()
+            .find(|t| t.kind() == kind)
+            .and_then(|t| t.content())
+            .map(|c| c.to_owned())
+    };
+    match event.kind {
+        Kind::TextNote | Kind::ChannelMessage => None,
+        Kind::Metadata => match Metadata::from_json(&event.content) {
+            Ok(m) => Some(format!(
+                "Updated profile metadata, name {:?}.",
+                m.name.unwrap_or_default()
+            )),
+            Err(_) => Some("Updated profile metadata.".to_owned()),
+        },
+        Kind::ContactList => Some(format!(
+            "Updated contact list, follows {} keys.",
+            event.public_keys().count()
+        )),
+        Kind::Reaction => Some(match event.content.as_str() {
+            "+" | "" => format!("Liked {} by {}.", target_event(), first_key()),
+            "-" => format!("Disliked {} by {}.", target_event(), first_key()),
+            r => format!("Reacted {:?} to {} by {}.", r, target_event(), first_key()),
+        }),
+        Kind::Repost | Kind::GenericRepost => {
+            Some(format!("Reposted {} by {}.", target_event(), first_key()))
+        }
+        Kind::EventDeletion => Some(format!(
+            "Requested deletion of {} events.",
+            event.event_ids().count() + event.coordinates().count()
+        )),
+        Kind::LongFormTextNote => Some(format!(
+            "Published long-form post {:?}.",
+            tag_content(TagKind::Title).unwrap_or_default()
+        )),
+        Kind::ZapReceipt => {
+            // the amount is found in the zap request embedded in the description
+            let msats = tag_content(TagKind::Description)
+                .and_then(|d| serde_json::from_str::<Value>(&d).ok())
+                .and_then(|r| {
+                    r["tags"].as_array()?.iter().find_map(|t| {
+                        match (t[0].as_str(), t[1].as_str()) {
+                            (Some("amount"), Some(a)) => a.parse::<u64>().ok(),
+                            _ => None,
+                        }
+                    })
+                });
+            Some(match msats {
+                Some(msats) => format!("Zapped {} sats to {}.", msats / 1000, first_key()),
+                None => format!("Zapped {}.", first_key()),
+            })
+        }
+        Kind::ChannelCreation | Kind::ChannelMetadata => {
+            match Metadata::from_json(&event.content) {
+                Ok(m) => Some(format!(
+                    "Created or updated channel {:?}.",
+                    m.name.unwrap_or_default()
+                )),
+                Err(_) => Some("Created or updated channel.".to_owned()),
+            }
+        }
+        Kind::RelayList => Some(format!(
+            "Updated relay list with {} relays.",
+            event
+                .tags
+                .iter()
+                .filter(
+                    |t| t.kind() == TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::R))
+                )
+                .count()
+        )),
+        _ => Some(format!("Published event of kind {}.", event.kind.as_u16())),
+    }
+}
+
+/// Handle the --fetch CLI argument
+/// Print all stored events matching the filters, stop on EOSE or timeout.
+/// With an event store only events newer than the synced ones are requested.
+pub(crate) async fn cli_fetch(
+    client: &Client,
+    ap: &Args,
+    filters: Vec<Filter>,
+    store: Option<&Mutex<EventStore>>,
+) -> Result<(), Error> {
+    if filters.is_empty() {
+        info!("Nothing to fetch. There are no subscriptions.");
+        return Ok(());
+    }
+    let (stored, filters) = match store {
+        Some(store) => store.lock().unwrap().catch_up(filters),
+        None => (Vec::new(), filters),
+    };
+    // get_events_of returns once all relays sent EOSE or the timeout expired
+    let timeout = Duration::from_secs(ap.fetch_timeout);
+    let synced_until = Timestamp::now();
+    let start = std::time::Instant::now();
+    let mut storage_failed = false;
+    let fetched = match client
+        .get_events_of(filters.clone(), EventSource::relays(Some(timeout)))
+        .await
+    {
+        Ok(events) => events,
+        Err(ref e) => {
+            error!(
+                "Error: fetching events from relays failed. Reported error {:?}.",
+                e
+            );
+            return Err(Error::FetchFailed);
+        }
+    };
+    debug!(
+        "Fetched {} events, {} events were stored already.",
+        fetched.len(),
+        stored.len()
+    );
+    if let Some(store) = store {
+        // without timeout all connected relays sent all their events,
+        // relays that failed to connect or lost the connection did not
+        let mut complete = start.elapsed() < timeout;
+        for relay in client
+            .pool()
+            .relays_with_flag(RelayServiceFlags::READ, FlagCheck::All)
+            .await
+            .values()
+        {
+            complete = complete && relay.is_connected().await;
+        }
+        let mut store = store.lock().unwrap();
+        let stored = store.add(&fetched).and_then(|_| match complete {
+            true => filters
+                .iter()
+                .try_for_each(|filter| store.mark_synced(filter, synced_until)),
+            false => Ok(()),
+        });
+        if let Err(ref e) = stored {
+            error!("Storing fetched events failed. Reported error is: {:?}", e);
+            storage_failed = true;
+        }
+    }
+    let mut ids: HashSet<EventId> = HashSet::new();
+    let mut events: Vec<nostr_sdk::Event> = stored
+        .into_iter()
+        .chain(fetched)
+        .filter(|e| ids.insert(e.id))
+        .collect();
+    events.sort_by_key(|e| e.created_at);
+    for event in &events {
+        print_json_spec(&event.as_json(), ap.output);
+        let relay_url = match client.database().event_seen_on_relays(&event.id).await {
+            Ok(Some(urls)) => urls.into_iter().next(),
+            _ => None,
+        };
+        handle_event(client, ap, event, relay_url.as_ref(), None).await;
+    }
+    match storage_failed {
+        true => Err(Error::StorageFailure),
+        false => Ok(()),
+    }
+}
+
+/// Handle the --query CLI argument
+/// Print the events of the local event store that match the filters.
+pub(crate) async fn cli_query(client: &Client, ap: &Args) -> Result<(), Error> {
+    let mut filters: Vec<Filter> = Vec::new();
+    for s in &ap.query {
+        filters.push(limit_filter(ap, str_to_filter(s)?));
+    }
+    let path = get_event_store_path(ap);
+    if !path.is_file() {
+        info!(
+            "Event store {:?} does not exist yet. Use '--store' to fill it.",
+            path
+        );
+    }
+    let store = match EventStore::open(&path) {
+        Ok(store) => store,
+        Err(ref e) => {
+            error!(
+                "Error: reading event store {:?} failed. Reported error {:?}.",
+                path, e
+            );
+            return Err(Error::EventStoreFailed);
+        }
+    };
+    for event in &store.query(&filters) {
+        print_json_spec(&event.as_json(), ap.output);
+        handle_event(client, ap, event, None, None).await;
+    }
     Ok(())
 }
 
-async fn subscribe_to_filter(client: &Client, ap: &Args, mut filter: Filter, filter_name: &str) {
+/// Apply the --limit-* arguments to a filter.
+fn limit_filter(ap: &Args, mut filter: Filter) -> Filter {
     if ap.limit_number != 0 {
         filter = filter.limit(ap.limit_number);
     }
@@ -3550,10 +7885,37 @@ async fn subscribe_to_filter(client: &Client, ap: &Args, mut filter: Filter, fil
     if ap.limit_future_hours != 0 {
         filter = filter.until(Timestamp::now() + Duration::new(ap.limit_future_hours * 60 * 60, 0));
     }
+    filter
+}
+
+/// Gift wraps are backdated, widen the window of a filter built by us
+/// so that they are not missed. Filters given by the user are kept as they are.
+fn widen_for_gift_wraps(filter: Filter) -> Filter {
+    match filter.since {
+        Some(since) => filter.since(since - Duration::from_secs(GIFT_WRAP_TWEAK)),
+        None => filter,
+    }
+}
+
+/// Returns the subscription id and the relays the subscription was sent to or not.
+async fn subscribe_to_filter(
+    client: &Client,
+    filter: Filter,
+    filter_name: &str,
+) -> Result<nostr_sdk::pool::Output<SubscriptionId>, Error> {
     info!("subscribe to {filter_name} initiated.");
     match client.subscribe(vec![filter], None).await {
-        Ok(..) => info!("subscribe to {filter_name} successful."),
-        Err(ref e) => error!("handle_notifications failed. Reported error is: {:?}", e),
+        Ok(output) => {
+            info!("subscribe to {filter_name} successful.");
+            Ok(output)
+        }
+        Err(ref e) => {
+            error!(
+                "subscribe to {filter_name} failed. Reported error is: {:?}",
+                e
+            );
+            Err(Error::SubscriptionFailed)
+        }
     }
 }
 
@@ -3572,4 +7934,634 @@ mod tests {
     fn test_help() {
         assert_eq!(help(), ());
     }
+
+    #[test]
+    fn test_str_to_tag() {
+        let ap = Args::new();
+        let tag = str_to_tag(&ap, "t:nostr").unwrap();
+        assert_eq!(tag.as_slice(), &["t", "nostr"]);
+        let id = "b3e392b11f5d4f28321cedd09303a748acfd0487aea5a7450b3481c60b6e4f87";
+        let tag = str_to_tag(&ap, &format!("e:{}", id)).unwrap();
+        assert_eq!(tag.as_slice(), &["e", id]);
+        // the relay hint of an nevent is kept
+        let nevent = Nip19Event::new(EventId::from_hex(id).unwrap(), ["wss://relay.example.org"])
+            .to_bech32()
+            .unwrap();
+        assert_eq!(
+            str_to_nevent(&nevent).unwrap().relays,
+            vec!["wss://relay.example.org".to_owned()]
+        );
+        let tag = str_to_tag(&ap, &format!("e:{}", nevent)).unwrap();
+        assert_eq!(tag.as_slice(), &["e", id, "wss://relay.example.org"]);
+        let tag = str_to_tag(&ap, r#"["r","https://example.com"]"#).unwrap();
+        assert_eq!(tag.as_slice(), &["r", "https://example.com"]);
+        assert!(str_to_tag(&ap, "nostr").is_err());
+        assert!(str_to_tag(&ap, "p:nobody").is_err());
+    }
+
+    #[test]
+    fn test_mine_pow_nonce() {
+        let pubkey = Keys::generate().public_key();
+        let created_at = Timestamp::now();
+        let tags = vec![Tag::hashtag("nostr")];
+        let nonce = mine_pow_nonce(&pubkey, &created_at, &Kind::TextNote, &tags, "pow", 8).unwrap();
+        let mut all_tags = tags.clone();
+        all_tags.push(nonce);
+        let id = EventId::new(&pubkey, &created_at, &Kind::TextNote, &all_tags, "pow");
+        assert!(id.check_pow(8));
+    }
+
+    #[test]
+    fn test_reply_tags() {
+        let own = Keys::generate();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let root = EventBuilder::text_note("root", [])
+            .to_event(&alice)
+            .unwrap();
+        // reply to the root: root gets the root marker, author gets a p tag
+        let tags = reply_tags(&root, None, &own.public_key());
+        assert_eq!(tags[0].as_slice(), &["e", &root.id.to_hex(), "", "root"]);
+        assert_eq!(tags[1].as_slice(), &["p", &alice.public_key().to_hex()]);
+        // reply to a reply: root is copied, participants are copied, own key is skipped
+        let reply = EventBuilder::text_note("reply", tags.clone())
+            .add_tags([Tag::public_key(own.public_key())])
+            .to_event(&bob)
+            .unwrap();
+        let tags = reply_tags(&reply, None, &own.public_key());
+        assert_eq!(tags.len(), 4);
+        assert_eq!(tags[0].as_slice(), &["e", &root.id.to_hex(), "", "root"]);
+        assert_eq!(tags[1].as_slice(), &["e", &reply.id.to_hex(), "", "reply"]);
+        assert_eq!(tags[2].as_slice(), &["p", &bob.public_key().to_hex()]);
+        assert_eq!(tags[3].as_slice(), &["p", &alice.public_key().to_hex()]);
+    }
+
+    #[test]
+    fn test_reaction_builder() {
+        let alice = Keys::generate();
+        let note = EventBuilder::text_note("hi", []).to_event(&alice).unwrap();
+        let relay = Some(UncheckedUrl::from("wss://relay.example.org"));
+        let reaction = reaction_builder(&note, relay, "+")
+            .to_event(&Keys::generate())
+            .unwrap();
+        assert_eq!(reaction.kind, Kind::Reaction);
+        assert_eq!(reaction.content, "+");
+        let tags: Vec<&[String]> = reaction.tags.iter().map(|t| t.as_slice()).collect();
+        assert_eq!(
+            tags[0],
+            &[
+                "e",
+                &note.id.to_hex(),
+                "wss://relay.example.org",
+                &alice.public_key().to_hex()
+            ]
+        );
+        assert_eq!(tags[1], &["p", &alice.public_key().to_hex()]);
+        assert_eq!(tags[2], &["k", "1"]);
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_dm() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let client = Client::new(&bob);
+        // NIP-04
+        let encrypted = nip04::encrypt(alice.secret_key(), &bob.public_key(), "hi").unwrap();
+        let event = EventBuilder::new(
+            Kind::EncryptedDirectMessage,
+            encrypted,
+            [Tag::public_key(bob.public_key())],
+        )
+        .to_event(&alice)
+        .unwrap();
+        let dm = decrypt_dm(&client, &event).await.unwrap();
+        assert_eq!(dm.sender, alice.public_key());
+        assert_eq!(dm.recipient, Some(bob.public_key()));
+        assert_eq!(dm.content, "hi");
+        assert_eq!(dm.protocol, DmProtocol::Nip04);
+        // NIP-17
+        let rumor = EventBuilder::private_msg_rumor(bob.public_key(), "ho", None)
+            .to_unsigned_event(alice.public_key());
+        let event = EventBuilder::gift_wrap(&alice, &bob.public_key(), rumor, None).unwrap();
+        let dm = decrypt_dm(&client, &event).await.unwrap();
+        assert_eq!(dm.sender, alice.public_key());
+        assert_eq!(dm.recipient, Some(bob.public_key()));
+        assert_eq!(dm.content, "ho");
+        assert_eq!(dm.protocol, DmProtocol::Nip17);
+        // not for us
+        let client = Client::new(Keys::generate());
+        assert!(decrypt_dm(&client, &event).await.is_err());
+    }
+
+    #[test]
+    fn test_limit_filter() {
+        let mut ap = Args::new();
+        ap.limit_number = 5;
+        ap.limit_hours = 1;
+        let filter = limit_filter(&ap, Filter::new().kind(Kind::TextNote));
+        assert_eq!(filter.limit, Some(5));
+        let since = filter.since.unwrap();
+        assert!(since <= Timestamp::now() - Duration::from_secs(60 * 60));
+        // the since of a user's filter is kept, also for gift wraps
+        let filter = limit_filter(&ap, Filter::new().kind(Kind::GiftWrap));
+        assert!(filter.since.unwrap() > since - Duration::from_secs(GIFT_WRAP_TWEAK));
+        // gift wraps are backdated, the window of our DM filter is widened
+        let filter = widen_for_gift_wraps(filter);
+        assert!(filter.since.unwrap() <= since - Duration::from_secs(GIFT_WRAP_TWEAK));
+    }
+
+    #[test]
+    fn test_subscribe_filter() {
+        let filter = str_to_filter(r##"{"kinds":[1],"#t":["nostr"],"limit":10}"##).unwrap();
+        assert_eq!(filter.limit, Some(10));
+        assert!(str_to_filter("not json").is_err());
+        let mut ap = Args::new();
+        ap.subscribe_filter = vec![
+            r#"tag={"kinds":[1]}"#.to_owned(),
+            r#"tag={"kinds":[7]}"#.to_owned(),
+            r#"{"kinds":[1]}"#.to_owned(),
+        ];
+        assert!(cli_subscribe_filter(&mut ap).is_err());
+        // same name replaces, missing name is rejected
+        assert_eq!(ap.creds.subscribed_filters.len(), 1);
+        assert_eq!(
+            ap.creds.subscribed_filters[0].filter,
+            Filter::new().kind(Kind::Reaction)
+        );
+        ap.unsubscribe_filter = vec!["tag".to_owned()];
+        assert!(cli_unsubscribe_filter(&mut ap).is_ok());
+        assert!(ap.creds.subscribed_filters.is_empty());
+    }
+
+    #[test]
+    fn test_event_summary() {
+        let ap = Args::new();
+        let keys = Keys::generate();
+        let note = EventBuilder::text_note("hi", []).to_event(&keys).unwrap();
+        assert_eq!(event_summary(&ap, &note), None);
+        let like = EventBuilder::reaction(&note, "+").to_event(&keys).unwrap();
+        assert!(event_summary(&ap, &like)
+            .unwrap()
+            .starts_with("Liked note1"));
+        let repost = EventBuilder::repost(&note, None).to_event(&keys).unwrap();
+        assert!(event_summary(&ap, &repost)
+            .unwrap()
+            .starts_with("Reposted note1"));
+        let contacts = EventBuilder::contact_list([Contact::new::<String>(
+            Keys::generate().public_key(),
+            None,
+            None,
+        )])
+        .to_event(&keys)
+        .unwrap();
+        assert_eq!(
+            event_summary(&ap, &contacts).unwrap(),
+            "Updated contact list, follows 1 keys."
+        );
+        let custom = EventBuilder::new(Kind::Custom(5555), "", [])
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(
+            event_summary(&ap, &custom).unwrap(),
+            "Published event of kind 5555."
+        );
+    }
+
+    #[test]
+    fn test_add_json_max() {
+        let mut ap = Args::new();
+        let bob = Keys::generate().public_key();
+        let event = EventBuilder::text_note("hi", [Tag::public_key(bob)])
+            .to_event(&Keys::generate())
+            .unwrap();
+        let url = Url::parse("wss://relay.example.org").unwrap();
+        let sub = SubscriptionId::new("sub");
+        let mut jsonv = json!({});
+        add_json_max(&ap, &mut jsonv, &event, Some(&url), Some(&sub));
+        assert_eq!(jsonv, json!({}));
+        ap.output = Output::JsonMax;
+        add_json_max(&ap, &mut jsonv, &event, Some(&url), Some(&sub));
+        assert_eq!(jsonv["event"]["id"], json!(event.id.to_hex()));
+        assert_eq!(jsonv["event"]["sig"], json!(event.sig.to_string()));
+        assert_eq!(jsonv["relay_url"], json!("wss://relay.example.org/"));
+        assert_eq!(jsonv["subscription_id"], json!("sub"));
+        assert_eq!(jsonv["p_tags"][0]["pubkey"], json!(bob.to_hex()));
+    }
+
+    #[test]
+    fn test_expand_nostr_uris() {
+        let mut ap = Args::new();
+        let joe = Keys::generate().public_key();
+        let ann = Keys::generate().public_key();
+        ap.creds.contacts.push(Contact::new(joe, None, Some("joe")));
+        let note = EventId::all_zeros().to_bech32().unwrap();
+        let content = format!(
+            "hi nostr:{} and nostr:{}, see nostr:{} or nostr:npub1broken",
+            joe.to_bech32().unwrap(),
+            ann.to_bech32().unwrap(),
+            note
+        );
+        let expanded = expand_nostr_uris(&ap, &content);
+        assert_eq!(
+            expanded,
+            format!(
+                "hi @joe and @{}, see {} or nostr:npub1broken",
+                short_bech32(&ann.to_bech32().unwrap()),
+                short_bech32(&note)
+            )
+        );
+    }
+
+    #[test]
+    fn test_event_store() {
+        let dir = env::temp_dir().join(format!(
+            "nostr-commander-test-{}",
+            &Keys::generate().public_key().to_hex()[..16]
+        ));
+        let path = dir.join(EVENT_STORE_FILE_DEFAULT);
+        let keys = Keys::generate();
+        let events: Vec<nostr_sdk::Event> = (0..3)
+            .map(|i| {
+                EventBuilder::text_note(format!("note {i}"), [])
+                    .custom_created_at(Timestamp::from(1_000 + i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        let mut store = EventStore::open(&path).unwrap();
+        assert_eq!(store.add(&events).unwrap(), 3);
+        // events delivered again, e.g. by another relay, are not stored twice
+        assert_eq!(store.add(&events[1..]).unwrap(), 0);
+        let store = EventStore::open(&path).unwrap();
+        assert_eq!(store.events.len(), 3);
+        let filter = Filter::new().author(keys.public_key()).limit(2);
+        let found = store.query(std::slice::from_ref(&filter));
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].id, events[1].id);
+        assert_eq!(found[1].id, events[2].id);
+        // stored events alone do not make the filter synced
+        let (stored, filters) = store.catch_up(vec![filter.clone()]);
+        assert_eq!(stored.len(), 2);
+        assert_eq!(filters[0].since, None);
+        // limited fetches might have missed events
+        let mut store = EventStore::open(&path).unwrap();
+        store.mark_synced(&filter, Timestamp::from(2_000)).unwrap();
+        assert!(store.synced.is_empty());
+        let filter = Filter::new().author(keys.public_key());
+        store.mark_synced(&filter, Timestamp::from(2_000)).unwrap();
+        let store = EventStore::open(&path).unwrap();
+        let (_, filters) = store.catch_up(vec![filter.clone().limit(2)]);
+        assert_eq!(filters[0].since, Some(Timestamp::from(2_000)));
+        // another filter is not synced
+        let other = Filter::new().author(keys.public_key()).kind(Kind::TextNote);
+        let (_, filters) = store.catch_up(vec![other]);
+        assert_eq!(filters[0].since, None);
+        // a later sync continuing the range extends it
+        let mut store = EventStore::open(&path).unwrap();
+        let (_, filters) = store.catch_up(vec![filter.clone()]);
+        store
+            .mark_synced(&filters[0], Timestamp::from(3_000))
+            .unwrap();
+        assert_eq!(
+            store.synced[&filter_key(&filter)],
+            SyncedRange {
+                since: None,
+                until: Timestamp::from(3_000)
+            }
+        );
+        // a range not reaching back far enough is not used
+        let filter = Filter::new().pubkey(keys.public_key());
+        store
+            .mark_synced(
+                &filter.clone().since(Timestamp::from(1_500)),
+                Timestamp::from(3_000),
+            )
+            .unwrap();
+        let (_, filters) = store.catch_up(vec![filter.clone().since(Timestamp::from(1_000))]);
+        assert_eq!(filters[0].since, Some(Timestamp::from(1_000)));
+        let (_, filters) = store.catch_up(vec![filter.since(Timestamp::from(1_600))]);
+        assert_eq!(filters[0].since, Some(Timestamp::from(3_000)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_differences() {
+        let local = Metadata::new().name("joe").about("local about");
+        let mut remote = Metadata::new().name("joe").about("remote about");
+        remote.nip05 = Some("joe@example.org".to_owned());
+        let diffs = metadata_differences(&local, &remote);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0]["field"], "about");
+        assert_eq!(diffs[1]["field"], "nip05");
+        assert_eq!(diffs[1]["local"], Value::Null);
+        assert!(metadata_differences(&local, &local).is_empty());
+    }
+
+    #[test]
+    fn test_outbox() {
+        let dir = env::temp_dir().join(format!(
+            "nostr-commander-test-{}",
+            &Keys::generate().public_key().to_hex()[..16]
+        ));
+        let outbox = Outbox::new(dir.join(OUTBOX_FILE_DEFAULT));
+        let event = EventBuilder::text_note("note", [])
+            .to_event(&Keys::generate())
+            .unwrap();
+        let good = Url::parse("wss://good.example.org").unwrap();
+        let bad = Url::parse("wss://bad.example.org").unwrap();
+        let urls = vec![good.clone(), bad.clone()];
+        let mut entry = OutboxEntry::new(event.clone(), &urls);
+        assert_eq!(entry.pending(), urls);
+        let mut output = nostr_sdk::pool::Output {
+            val: event.id,
+            success: HashSet::new(),
+            failed: std::collections::HashMap::new(),
+        };
+        output.success.insert(good.clone());
+        output
+            .failed
+            .insert(bad.clone(), Some("blocked".to_owned()));
+        entry.record(&urls, Ok(&output));
+        assert_eq!(entry.pending(), vec![bad.clone()]);
+        assert_eq!(entry.relays[1].message, Some("blocked".to_owned()));
+        assert_eq!(entry.attempts, 1);
+        assert!(entry.next_attempt > Timestamp::now());
+        // the wait doubles with each attempt, up to the maximum
+        assert_eq!(outbox_backoff(1), OUTBOX_BACKOFF_DEFAULT);
+        assert_eq!(outbox_backoff(3), 4 * OUTBOX_BACKOFF_DEFAULT);
+        assert_eq!(outbox_backoff(100), OUTBOX_BACKOFF_MAX);
+        outbox.queue(entry.clone()).unwrap();
+        // queueing the same event again replaces it
+        outbox.queue(entry).unwrap();
+        let entries = outbox.load().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].event.id, event.id);
+        assert_eq!(entries[0].pending(), vec![bad]);
+        // an empty outbox has no file
+        outbox.save(&[]).unwrap();
+        assert!(!outbox.path.exists());
+        assert!(outbox.load().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_wait_ok_quorum() {
+        let mut ap = Args::new();
+        ap.output = Output::Json;
+        let outbox = Outbox::new(env::temp_dir().join(OUTBOX_FILE_DEFAULT));
+        let event = EventBuilder::text_note("note", [])
+            .to_event(&Keys::generate())
+            .unwrap();
+        let urls = vec![
+            Url::parse("wss://good.example.org").unwrap(),
+            Url::parse("wss://bad.example.org").unwrap(),
+        ];
+        let mut entry = OutboxEntry::new(event, &urls);
+        entry.relays[0].accepted = true;
+        outbox.sent.lock().unwrap().push(entry);
+        assert!(cli_wait_ok(&ap, &outbox).is_ok());
+        ap.quorum = 2;
+        assert!(matches!(
+            cli_wait_ok(&ap, &outbox),
+            Err(Error::QuorumNotReached)
+        ));
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(Error::ReadingCredentialsFailed.exit_code(), 3);
+        assert_eq!(Error::InvalidKey.exit_code(), 4);
+        assert_eq!(Error::CannotConnectToRelays.exit_code(), 5);
+        assert_eq!(Error::DmFailed.exit_code(), 6);
+        assert_eq!(Error::QuorumNotReached.exit_code(), 7);
+        // the first failure determines the exit code
+        let result: Result<(), Error> = Err(Error::PublishFailed);
+        let result: Result<(), Error> = result.and(Err(Error::QuorumNotReached));
+        assert_eq!(result.unwrap_err().exit_code(), 6);
+    }
+
+    #[test]
+    fn test_relay_flags() {
+        // relays stored before the flags existed keep being used for everything
+        let relay: Relay =
+            serde_json::from_str(r#"{"url":"wss://relay.example.org","proxy":null}"#).unwrap();
+        assert!(relay.read && relay.write && relay.inbox && relay.outbox);
+        let relay = relay.with_flags(&[RelayFlag::Read, RelayFlag::Inbox]);
+        assert!(relay.read && !relay.write && relay.inbox && !relay.outbox);
+        let relay = Relay::new(relay.url, None).with_flags(&[]);
+        assert!(relay.read && relay.write && relay.inbox && relay.outbox);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_socks5_auth_bridge() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        // upstream proxy that requires username and password and echoes the data
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut s, _) = upstream.accept().await.unwrap();
+            let mut head = [0u8; 2];
+            s.read_exact(&mut head).await.unwrap();
+            let mut methods = vec![0u8; head[1] as usize];
+            s.read_exact(&mut methods).await.unwrap();
+            assert!(methods.contains(&2));
+            s.write_all(&[5, 2]).await.unwrap();
+            let mut auth = vec![0u8; 2 + 3 + 1 + 6];
+            s.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth[2..5], b"joe");
+            assert_eq!(&auth[6..], b"secret");
+            s.write_all(&[1, 0]).await.unwrap();
+            let mut request = [0u8; 5];
+            s.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[3], 3);
+            let mut rest = vec![0u8; request[4] as usize + 2];
+            s.read_exact(&mut rest).await.unwrap();
+            assert_eq!(&rest[..request[4] as usize], b"relay.example.org");
+            s.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+            let mut buf = [0u8; 4];
+            s.read_exact(&mut buf).await.unwrap();
+            s.write_all(&buf).await.unwrap();
+        });
+        let auth = ProxyAuth::from_str("joe:secret").unwrap();
+        let bridges = Socks5Bridges::default();
+        let bridge = bridges
+            .get(upstream_addr, &auth, "relay.example.org")
+            .unwrap();
+        // relays using the same proxy share the bridge
+        let shared = bridges
+            .clone()
+            .get(upstream_addr, &auth, "other.example.org")
+            .unwrap();
+        assert_eq!(bridge, shared);
+        // only the hosts of the relays can be reached
+        assert!(
+            tokio_socks::tcp::Socks5Stream::connect(bridge, ("example.com", 443))
+                .await
+                .is_err()
+        );
+        // the client connects to the bridge without authentication
+        let mut stream =
+            tokio_socks::tcp::Socks5Stream::connect(bridge, ("relay.example.org", 443))
+                .await
+                .unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert!(ProxyAuth::from_str("nopassword").is_err());
+        // the bridge stops when dropped
+        drop(bridges);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(tokio::net::TcpStream::connect(bridge).await.is_err());
+    }
+
+    #[test]
+    fn test_relay_list() {
+        let url = |u: &str| Url::parse(u).unwrap();
+        let relays = vec![
+            Relay::new(url("wss://both.example.org"), None),
+            Relay::new(url("wss://inbox.example.org"), None).with_flags(&[RelayFlag::Inbox]),
+            Relay::new(url("wss://outbox.example.org"), None)
+                .with_flags(&[RelayFlag::Write, RelayFlag::Outbox]),
+            Relay::new(url("wss://private.example.org"), None)
+                .with_flags(&[RelayFlag::Read, RelayFlag::Write]),
+        ];
+        let event = relay_list_builder(&relays)
+            .to_event(&Keys::generate())
+            .unwrap();
+        assert_eq!(event.kind, Kind::RelayList);
+        let imported: Vec<Relay> = nip65::extract_relay_list(&event)
+            .map(|(u, m)| Relay::from_relay_metadata(u.clone(), *m))
+            .collect();
+        // relays that are neither inbox nor outbox are not published
+        assert_eq!(imported.len(), 3);
+        assert!(imported[0].read && imported[0].write && imported[0].inbox && imported[0].outbox);
+        assert!(imported[1].read && !imported[1].write && imported[1].inbox);
+        assert!(!imported[2].read && imported[2].write && imported[2].outbox);
+    }
+
+    #[test]
+    fn test_inbox_routing() {
+        let url = |u: &str| Url::parse(u).unwrap();
+        let relays = vec![
+            Relay::new(url("wss://both.example.org"), None),
+            Relay::new(url("wss://inbox.example.org"), None).with_flags(&[RelayFlag::Inbox]),
+            Relay::new(url("wss://outbox.example.org"), None).with_flags(&[RelayFlag::Outbox]),
+        ];
+        let list = relay_list_builder(&relays)
+            .to_event(&Keys::generate())
+            .unwrap();
+        // outbox-only relays are not read by their owner
+        assert_eq!(
+            inbox_relay_urls(&list),
+            vec![
+                url("wss://both.example.org"),
+                url("wss://inbox.example.org")
+            ]
+        );
+
+        let contact = Keys::generate().public_key();
+        let mut ap = Args::new();
+        ap.creds.contacts.push(Contact::new(
+            contact,
+            Some(UncheckedUrl::from("wss://hint.example.org")),
+            Some("joe"),
+        ));
+        let routing = InboxRouting::new(&ap);
+        assert!(routing.access.allowed);
+        assert_eq!(
+            routing.hints.get(&contact),
+            Some(&url("wss://hint.example.org"))
+        );
+        // gift wraps go to the DM relays (kind 10050), other events to the inbox relays
+        let dm_list = EventBuilder::new(
+            Kind::Custom(DM_RELAY_LIST_KIND),
+            "",
+            [Tag::from_standardized_without_cell(TagStandard::Relay(
+                UncheckedUrl::from("wss://dm.example.org"),
+            ))],
+        )
+        .to_event(&Keys::generate())
+        .unwrap();
+        routing.cache.lock().unwrap().insert(
+            contact,
+            RecipientRelays {
+                inbox: inbox_relay_urls(&list),
+                dm: dm_relay_urls(&dm_list),
+            },
+        );
+        assert_eq!(
+            routing.relays_for(&contact, true),
+            vec![url("wss://dm.example.org")]
+        );
+        assert_eq!(routing.relays_for(&contact, false), inbox_relay_urls(&list));
+        // a direct connection would bypass the proxy of our relays
+        let proxy = Some(SocketAddr::from(([127, 0, 0, 1], 9050)));
+        ap.creds
+            .relays
+            .push(Relay::new(url("wss://relay.example.org"), proxy));
+        assert!(!InboxRouting::new(&ap).access.allowed);
+        ap.proxy_all = proxy;
+        assert!(InboxRouting::new(&ap).access.allowed);
+    }
+
+    #[test]
+    fn test_merge_contacts() {
+        let hint = |u: &str| Some(UncheckedUrl::from(u));
+        let (jane, joe, jim) = (
+            Keys::generate().public_key(),
+            Keys::generate().public_key(),
+            Keys::generate().public_key(),
+        );
+        let mut local = vec![
+            Contact::new(jane, None, Some("jane")),
+            Contact::new(joe, hint("wss://joe.example.org"), None::<String>),
+        ];
+        let published = vec![
+            Contact::new(jane, hint("wss://jane.example.org"), Some("janey")),
+            Contact::new(joe, hint("wss://other.example.org"), Some("joe")),
+            // alias is taken by jane already
+            Contact::new(jim, None, Some("jane")),
+        ];
+        let added = merge_contacts(&mut local, published.clone(), &[]);
+        assert_eq!(added, vec![Contact::new(jim, None, None::<String>)]);
+        assert_eq!(local.len(), 3);
+        assert_eq!(local[0].alias.as_deref(), Some("jane"));
+        assert_eq!(local[0].relay_url, hint("wss://jane.example.org"));
+        assert_eq!(local[1].alias.as_deref(), Some("joe"));
+        assert_eq!(local[1].relay_url, hint("wss://joe.example.org"));
+        // merging again adds nothing
+        let again = local.clone();
+        assert!(merge_contacts(&mut local, again, &[]).is_empty());
+        // removed locally since the last sync, it is not added again
+        let synced = [jane, joe, jim];
+        local.retain(|c| c.public_key != jim);
+        assert!(merge_contacts(&mut local, published.clone(), &synced).is_empty());
+        assert_eq!(local.len(), 2);
+        // removed by another client since the last sync, it is removed locally
+        let published = published[..1].to_vec();
+        assert!(merge_contacts(&mut local, published, &synced).is_empty());
+        assert_eq!(local.len(), 1);
+        assert_eq!(local[0].public_key, jane);
+    }
+
+    #[test]
+    fn test_is_sent_dm() {
+        let own = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let mut ap = Args::new();
+        ap.creds.public_key_bech32 = own.to_bech32().unwrap();
+        let dm = |sender, recipient| DecryptedDm {
+            sender,
+            recipient: Some(recipient),
+            content: "hi".to_owned(),
+            created_at: Timestamp::now(),
+            protocol: DmProtocol::Nip17,
+        };
+        assert!(is_sent_dm(&ap, &dm(own, other)));
+        assert!(!is_sent_dm(&ap, &dm(other, own)));
+        // a note to self is shown
+        assert!(!is_sent_dm(&ap, &dm(own, own)));
+    }
 }